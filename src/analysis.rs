@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use always_online_torrent_trackers::tracker_check::CandidateProfile;
+
+/// How many of the most shared addresses are listed
+const TOP_SHARED_IPS: usize = 10;
+
+/// Lists the IPs that several distinct tracker hosts resolved to, most shared first.
+/// Lots of them mean the list is less diverse than its length suggests
+pub fn shared_ips_report(profiles: &[&CandidateProfile]) -> String {
+    let mut hosts_by_ip: HashMap<IpAddr, Vec<&str>> = HashMap::new();
+    profiles.iter()
+        .flat_map(|profile| profile.addrs.iter().map(move |addr| (addr.ip(), profile.candidate.host.as_str())))
+        .for_each(|(ip, host)| {
+            let hosts = hosts_by_ip.entry(ip).or_default();
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        });
+    let mut shared = hosts_by_ip.into_iter()
+        .filter(|(_, hosts)| hosts.len() > 1)
+        .collect::<Vec<_>>();
+    if shared.is_empty() {
+        return String::from("No IP is shared by more than one tracker host");
+    }
+    shared.sort_by(|(a_ip, a_hosts), (b_ip, b_hosts)| b_hosts.len().cmp(&a_hosts.len()).then(a_ip.cmp(b_ip)));
+    let total = shared.len();
+    let mut lines = vec![format!("IPs shared by more than one tracker host: {}", total)];
+    lines.extend(shared.into_iter()
+        .take(TOP_SHARED_IPS)
+        .map(|(ip, hosts)| format!("{} ({} hosts): {}", ip, hosts.len(), hosts.join(", "))));
+    lines.join("\n")
+}
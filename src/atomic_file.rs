@@ -0,0 +1,24 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use tokio::io;
+
+/// Writes `contents` to a temporary file next to `path`, then renames it into place.
+/// The rename is atomic on the same filesystem, so readers see either the old file or the complete new one
+pub async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+    let temp_path = temp_path(path);
+    tokio::fs::write(&temp_path, contents).await?;
+    if let Err(err) = tokio::fs::rename(&temp_path, path).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Hidden, and unique per process so concurrent runs don't clobber each other's half-written files
+fn temp_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(file_name)
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+use futures::future::FutureExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use tokio::io;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+
+use crate::candidates::TrackerCandidate;
+use crate::tracker_check::{self, CandidateProfile, CheckError, CheckOptions};
+
+pub type CheckResult = Result<CandidateProfile, CheckError>;
+
+/// Receives every result of `check_candidates` as soon as it's known, e.g. to write it out
+pub trait ResultSink {
+    /// An error aborts the whole batch
+    fn accept(&mut self, candidate: &TrackerCandidate, result: &CheckResult) -> io::Result<()>;
+}
+
+/// Hands the results over to another thread, a closed receiver is an error
+impl ResultSink for Sender<(TrackerCandidate, CheckResult)> {
+    fn accept(&mut self, candidate: &TrackerCandidate, result: &CheckResult) -> io::Result<()> {
+        self.send((candidate.clone(), result.clone()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The result receiver was dropped"))
+    }
+}
+
+/// How a batch of checks is paced
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    pub concurrency: usize,
+    /// Most checks of the same host running at once, on top of the `concurrency` limit
+    pub per_host_concurrency: Option<usize>,
+    /// Most checks started per second, on top of the `concurrency` limit
+    pub rate: Option<f64>,
+    pub deadline: Option<Instant>,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 10,
+            per_host_concurrency: None,
+            rate: None,
+            deadline: None
+        }
+    }
+}
+
+/// Candidates still unchecked, or waiting for a permit, at the `deadline` are reported as `Timeout`, and as `Cancelled`
+/// once `cancel` resolves.
+/// `sink` sees every result as soon as it's known, the returned ones are in the order of `candidates`
+pub async fn check_candidates<'a>(
+    candidates: impl Iterator<Item = &'a TrackerCandidate>,
+    batch_options: &BatchOptions,
+    check_options: &CheckOptions,
+    cancel: impl Future<Output = ()>,
+    sink: &mut dyn ResultSink
+) -> io::Result<Vec<CheckResult>> {
+    let semaphore = Rc::new(Semaphore::new(batch_options.concurrency));
+    let cancel = cancel.shared();
+    let candidates = candidates.collect::<Vec<_>>();
+    // a host listed with many ports would otherwise take several probes at once, and may rate limit all of them
+    let mut host_semaphores: HashMap<&str, Rc<Semaphore>> = HashMap::new();
+    if let Some(per_host_concurrency) = batch_options.per_host_concurrency {
+        for candidate in &candidates {
+            host_semaphores.entry(candidate.host.as_str())
+                .or_insert_with(|| Rc::new(Semaphore::new(per_host_concurrency)));
+        }
+    }
+    // checks take turns on the interval after getting their permit, so starts are spaced out even when permits are free
+    let launches = batch_options.rate.map(|rate| {
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Rc::new(Mutex::new(interval))
+    });
+    let mut pending = candidates.into_iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            let semaphore_local_ref = semaphore.clone();
+            let host_semaphore = host_semaphores.get(candidate.host.as_str()).cloned();
+            let launches = launches.clone();
+            let cancel = cancel.clone();
+            async move {
+                let check = async {
+                    // the host's permit comes first, so waiting on it doesn't hold up a global one
+                    let _host_permit = match &host_semaphore {
+                        Some(host_semaphore) => Some(host_semaphore.acquire().await.expect("Semaphore to be operating")),
+                        None => None
+                    };
+                    let _permit = semaphore_local_ref.acquire().await.expect("Semaphore to be operating");
+                    if let Some(launches) = &launches {
+                        launches.lock().await.tick().await;
+                    }
+                    tracker_check::check_candidate(candidate.clone(), check_options).await
+                };
+                let check = async {
+                    match batch_options.deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline, check).await.unwrap_or(Err(CheckError::Timeout)),
+                        None => check.await
+                    }
+                };
+                let res = tokio::select! {
+                    res = check => res,
+                    _ = cancel => Err(CheckError::Cancelled)
+                };
+                match &res {
+                    Ok(profile) => { debug!("Success: {:?}", profile) }
+                    Err(err) => { debug!("Failure: {}: {}", candidate, err) }
+                }
+                (index, candidate, res)
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut profiles = (0..pending.len()).map(|_| None).collect::<Vec<_>>();
+    while let Some((index, candidate, res)) = pending.next().await {
+        sink.accept(candidate, &res)?;
+        profiles[index] = Some(res);
+    }
+    Ok(profiles.into_iter()
+        .map(|res| res.expect("Every check to have completed"))
+        .collect())
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+
+/// Lists and dicts nested deeper than this are rejected, the input comes from untrusted trackers
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_bytes()),
+            _ => None
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => Some(*int),
+            _ => None
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None
+        }
+    }
+}
+
+/// Decodes a single bencoded value, ignoring anything that trails it
+pub fn decode(input: &[u8]) -> Result<Value, &'static str> {
+    let (value, _) = decode_value(input, 0)?;
+    Ok(value)
+}
+
+fn decode_value(input: &[u8], depth: usize) -> Result<(Value, &[u8]), &'static str> {
+    if depth >= MAX_DEPTH && matches!(input.first(), Some(b'l') | Some(b'd')) {
+        return Err("Bencode nested too deeply");
+    }
+    match input.first() {
+        Some(b'i') => {
+            let (int, rest) = decode_int(&input[1..], b'e')?;
+            Ok((Value::Int(int), rest))
+        }
+        Some(b'l') => {
+            let mut rest = &input[1..];
+            let mut list = Vec::new();
+            while rest.first() != Some(&b'e') {
+                let (value, next) = decode_value(rest, depth + 1)?;
+                list.push(value);
+                rest = next;
+            }
+            Ok((Value::List(list), &rest[1..]))
+        }
+        Some(b'd') => {
+            let mut rest = &input[1..];
+            let mut dict = BTreeMap::new();
+            while rest.first() != Some(&b'e') {
+                let (key, next) = decode_bytes(rest)?;
+                let (value, next) = decode_value(next, depth + 1)?;
+                dict.insert(key, value);
+                rest = next;
+            }
+            Ok((Value::Dict(dict), &rest[1..]))
+        }
+        Some(b'0'..=b'9') => {
+            let (bytes, rest) = decode_bytes(input)?;
+            Ok((Value::Bytes(bytes), rest))
+        }
+        Some(_) => Err("Unexpected bencode type prefix"),
+        None => Err("Unexpected end of bencoded input")
+    }
+}
+
+fn decode_bytes(input: &[u8]) -> Result<(Vec<u8>, &[u8]), &'static str> {
+    let (len, rest) = decode_int(input, b':')?;
+    if len < 0 || len as usize > rest.len() {
+        return Err("Bencoded string length out of bounds");
+    }
+    let (bytes, rest) = rest.split_at(len as usize);
+    Ok((bytes.to_vec(), rest))
+}
+
+fn decode_int(input: &[u8], terminator: u8) -> Result<(i64, &[u8]), &'static str> {
+    let end = input.iter()
+        .position(|byte| *byte == terminator)
+        .ok_or("Unterminated bencoded integer")?;
+    let int = std::str::from_utf8(&input[..end])
+        .map_err(|_| "Bencoded integer is not valid UTF-8")?
+        .parse()
+        .map_err(|_| "Bencoded integer is not numeric")?;
+    Ok((int, &input[end + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_nested_values() {
+        let value = decode(b"d8:intervali1800e5:peersl4:abcdee").unwrap();
+        assert_eq!(value.get("interval").and_then(Value::as_int), Some(1800));
+        assert_eq!(value.get("peers").and_then(Value::as_list).map(<[Value]>::len), Some(1));
+    }
+
+    #[test]
+    fn accepts_nesting_up_to_the_limit() {
+        let input = [vec![b'l'; MAX_DEPTH], vec![b'e'; MAX_DEPTH]].concat();
+        assert!(decode(&input).is_ok());
+    }
+
+    #[test]
+    fn rejects_deep_nesting_without_overflowing_the_stack() {
+        let input = vec![b'l'; 20_000];
+        assert_eq!(decode(&input), Err("Bencode nested too deeply"));
+    }
+}
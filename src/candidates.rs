@@ -1,26 +1,37 @@
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
-use std::error::Error;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::net::Ipv6Addr;
+use std::str::FromStr;
 use tokio::io;
+use crate::atomic_file;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TransportType {
     UDP,
     HTTP,
     HTTPS
 }
 
-impl TransportType {
-    fn to_string(&self) -> &'static str {
-        match self {
+/// The URL scheme
+impl fmt::Display for TransportType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             Self::UDP => "udp",
             Self::HTTP => "http",
             Self::HTTPS => "https"
-        }
+        })
     }
+}
+
+/// Schemes are case-insensitive
+impl FromStr for TransportType {
+    type Err = &'static str;
 
-    fn from_string(s: &str) -> Result<TransportType, &'static str> {
-        match s {
+    fn from_str(s: &str) -> Result<TransportType, &'static str> {
+        match s.to_ascii_lowercase().as_str() {
             "udp" => Ok(Self::UDP),
             "http" => Ok(Self::HTTP),
             "https" => Ok(Self::HTTPS),
@@ -29,89 +40,520 @@ impl TransportType {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+impl TransportType {
+    /// Port assumed when a tracker URL leaves it out
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Self::UDP => 6969,
+            Self::HTTP => 80,
+            Self::HTTPS => 443
+        }
+    }
+}
+
+/// Candidates path meaning stdin
+pub const STDIN_PATH: &str = "-";
+
+/// Path assumed when a tracker URL has none
+pub const DEFAULT_SUFFIX: &str = "/announce";
+
+#[derive(Clone, Debug)]
 pub struct TrackerCandidate {
     pub host: String,
     pub port: u16,
     pub transport_type: TransportType,
-    pub suffix: Option<String>
+    pub suffix: Option<String>,
+    /// `key=value` pairs from a `# ...` comment trailing the candidate's line. They don't make the
+    /// candidate a different tracker, so equality and hashing only look at the URL, see `canonical_suffix`
+    pub tags: BTreeMap<String, String>,
+    /// That comment as written, tags included, kept so rewriting the file leaves it as it was
+    pub comment: Option<String>,
+}
+
+impl PartialEq for TrackerCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.host, self.port, &self.transport_type, self.canonical_suffix()) == (&other.host, other.port, &other.transport_type, other.canonical_suffix())
+    }
+}
+
+impl Eq for TrackerCandidate {}
+
+impl Hash for TrackerCandidate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (&self.host, self.port, &self.transport_type, self.canonical_suffix()).hash(state);
+    }
 }
 
 impl PartialOrd<Self> for TrackerCandidate {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.to_string().partial_cmp(&other.to_string())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for TrackerCandidate {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.to_string().cmp(&other.to_string())
+        let canonical = |candidate: &TrackerCandidate| format!("{}://{}{}", candidate.transport_type, candidate.authority(), candidate.canonical_suffix());
+        canonical(self).cmp(&canonical(other))
     }
 }
 
-impl TrackerCandidate {
-    pub fn to_string(&self) -> String {
-        return format!("{}://{}:{}{}",
-            self.transport_type.to_string(), self.host, self.port, self.suffix.as_ref().unwrap_or(&String::from(""))
-        );
+impl fmt::Display for TrackerCandidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", self.transport_type, self.authority(), self.suffix.as_deref().unwrap_or(""))
     }
+}
 
-    pub fn from_string(string: &str) -> Result<TrackerCandidate, &'static str> {
-        let parts = string.split(':').collect::<Vec<_>>();
-        if parts.len() != 3 {
-            return Err("Invalid format. Expecting two ':'");
-        }
-        let transport_type = TransportType::from_string(parts[0])?;
-        if !parts[1].starts_with("//") {
-            return Err("Invalid format. Expecting proto://host:port[/suffix]. Missing '://' after proto");
-        }
-        let (_, host_str) = parts[1].split_at(2);
-        let host = String::from(host_str);
-        let suffix_index = parts[2].find('/');
-        let port;
-        let mut suffix = None;
-        if suffix_index.is_some() {
-            let (port_str, suffix_str) = parts[2].split_at(suffix_index.unwrap());
-            port = port_str.parse().map_err(|_| "Expected port to be a numeric value")?;
-            suffix = Some(String::from(suffix_str));
+impl FromStr for TrackerCandidate {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<TrackerCandidate, &'static str> {
+        TrackerCandidate::from_url(s)
+    }
+}
+
+impl TrackerCandidate {
+    /// The `host:port` part of the URL, with IPv6 literals wrapped in brackets and their zone id's `%` escaped
+    pub fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host.replacen('%', "%25", 1), self.port)
         } else {
-            port = parts[2].parse().map_err(|_| "Expected port to be a numeric value")?;
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    /// The suffix the candidate is compared by. UDP trackers listed on a bare `host:port` are announced
+    /// to at `DEFAULT_SUFFIX` anyway, so both spellings are one tracker
+    fn canonical_suffix(&self) -> &str {
+        match (&self.suffix, &self.transport_type) {
+            (Some(suffix), _) => suffix,
+            (None, TransportType::UDP) => DEFAULT_SUFFIX,
+            (None, _) => ""
+        }
+    }
+
+    /// Validates and normalizes the pieces the way `from_url` does: the host is lowercased and a trailing `/`
+    /// is dropped from the suffix.
+    /// IPv6 hosts are given without the brackets. A link-local one can name its interface in a
+    /// zone id, `fe80::1%eth0`, which URLs escape as `fe80::1%25eth0` (RFC 6874). Zone ids keep their case
+    pub fn new(transport_type: TransportType, host: &str, port: u16, suffix: Option<&str>) -> Result<TrackerCandidate, &'static str> {
+        let host = host.replacen("%25", "%", 1);
+        let (ip, zone) = match host.split_once('%') {
+            Some((ip, zone)) => (ip, Some(zone)),
+            None => (host.as_str(), None)
+        };
+        if ip.is_empty() {
+            return Err("Expected a host");
+        }
+        if ip.contains(':') && ip.parse::<Ipv6Addr>().is_err() {
+            return Err("Expected a host containing ':' to be an IPv6 address");
+        }
+        if !ip.chars().all(|char| char.is_ascii_alphanumeric() || "-._:".contains(char)) {
+            return Err("Expected the host to be a domain name or an IP address");
+        }
+        if zone.is_some_and(|zone| !ip.contains(':') || zone.is_empty()
+            || !zone.chars().all(|char| char.is_ascii_alphanumeric() || "-._~".contains(char))) {
+            return Err("Expected a zone id to follow an IPv6 address and to be made of unreserved characters");
         }
-        return Ok(TrackerCandidate {
-            host: String::from(host),
+        let suffix = suffix.unwrap_or("");
+        if !suffix.is_empty() && !suffix.starts_with('/') {
+            return Err("Expected the suffix to start with '/'");
+        }
+        let suffix = TrackerCandidate::normalize_suffix(suffix)?;
+        Ok(TrackerCandidate {
+            host: match zone {
+                Some(zone) => format!("{}%{}", ip.to_ascii_lowercase(), zone),
+                None => ip.to_ascii_lowercase()
+            },
             port,
             transport_type,
-            suffix
+            suffix,
+            tags: BTreeMap::new(),
+            comment: None
         })
     }
+
+    /// The line the candidates file lists this candidate on, with its comment. Tags given
+    /// without one, as results files carry them, make up the comment instead
+    pub fn to_line(&self) -> String {
+        if let Some(comment) = &self.comment {
+            return format!("{} # {}", self, comment);
+        }
+        if self.tags.is_empty() {
+            return self.to_string();
+        }
+        let tags = self.tags.iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        format!("{} # {}", self, tags.join(" "))
+    }
+
+    /// Kept for the candidates file format, which is just one tracker URL per line.
+    /// Lists often leave the scheme out of UDP trackers, so `host:port[/suffix]` is taken to be `udp://`.
+    /// The port is required there, a lone word is more likely a stray line than a tracker.
+    /// A `#` after whitespace starts the line's comment, its `key=value` words are the candidate's tags,
+    /// e.g. `udp://host:6969/announce # region=eu tier=1`. The whole comment is kept as the `comment`
+    pub fn from_string(string: &str) -> Result<TrackerCandidate, &'static str> {
+        let comment_index = string.find(" #").into_iter().chain(string.find("\t#")).min();
+        let (string, comment) = match comment_index {
+            Some(comment_index) => (string[..comment_index].trim_end(), Some(string[comment_index + 2..].trim()).filter(|comment| !comment.is_empty())),
+            None => (string, None)
+        };
+        let mut candidate = if string.contains("://") {
+            TrackerCandidate::from_url(string)?
+        } else {
+            let authority = string.split('/').next().unwrap_or_default();
+            if TrackerCandidate::parse_authority(authority)?.1.is_none() {
+                return Err("Invalid format. Expecting proto://host[:port][/suffix], or host:port[/suffix] for UDP");
+            }
+            TrackerCandidate::from_url(&format!("udp://{}", string))?
+        };
+        candidate.tags = comment.map(TrackerCandidate::parse_tags).unwrap_or_default();
+        candidate.comment = comment.map(String::from);
+        Ok(candidate)
+    }
+
+    /// The `key=value` words of a comment
+    fn parse_tags(comment: &str) -> BTreeMap<String, String> {
+        comment.split_whitespace()
+            .filter_map(|word| word.split_once('='))
+            .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+            .map(|(key, value)| (String::from(key), String::from(value)))
+            .collect()
+    }
+
+    /// Parses `scheme://host[:port][/path]` along the lines of RFC 3986.
+    /// The scheme and host are case-insensitive and lowercased, the missing pieces are defaulted.
+    /// Userinfo, query strings and fragments have no place in a tracker candidate and are rejected
+    pub fn from_url(url: &str) -> Result<TrackerCandidate, &'static str> {
+        let (scheme, rest) = url.split_once("://")
+            .ok_or("Invalid format. Expecting proto://host[:port][/suffix]. Missing '://' after proto")?;
+        let transport_type = scheme.parse::<TransportType>()?;
+        if rest.contains('#') {
+            return Err("Expected no fragment in the tracker URL");
+        }
+        if rest.contains('?') {
+            return Err("Expected no query string in the tracker URL");
+        }
+        let (authority, path) = match rest.find('/') {
+            Some(path_index) => rest.split_at(path_index),
+            None => (rest, "")
+        };
+        if authority.contains('@') {
+            return Err("Expected no user info in the tracker URL");
+        }
+        let (host, port) = TrackerCandidate::parse_authority(authority)?;
+        let port = match port {
+            Some(port) => port,
+            None => transport_type.default_port()
+        };
+        TrackerCandidate::new(transport_type, host, port, Some(path))
+    }
+
+    /// An empty port, as in `host:`, counts as missing. The host is validated by `new`
+    fn parse_authority(authority: &str) -> Result<(&str, Option<u16>), &'static str> {
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, port) = bracketed.split_once(']')
+                .ok_or("Invalid format. Expecting [ipv6]:port")?;
+            host.split('%').next().unwrap_or_default().parse::<Ipv6Addr>()
+                .map_err(|_| "Expected a valid IPv6 address between '[' and ']'")?;
+            let port = match port {
+                "" => None,
+                port => Some(port.strip_prefix(':').ok_or("Invalid format. Expecting [ipv6]:port")?)
+            };
+            (host, port)
+        } else {
+            let parts = authority.split(':').collect::<Vec<_>>();
+            match parts.len() {
+                1 => (parts[0], None),
+                2 => (parts[0], Some(parts[1])),
+                _ => return Err("Invalid format. Expecting at most one ':' between host and port, IPv6 hosts must be enclosed in '[' and ']'")
+            }
+        };
+        // port 0 can't be connected to, so such a candidate could only ever fail its check
+        let port = match port.filter(|port| !port.is_empty()) {
+            Some(port) => match port.parse::<u16>() {
+                Ok(0) => return Err("Expected a non-zero port"),
+                Ok(port) => Some(port),
+                Err(_) => return Err("Expected port to be a number between 1 and 65535")
+            },
+            None => None
+        };
+        Ok((host, port))
+    }
+
+    /// Trailing slashes are dropped, so `/announce/` and `/announce` are the same candidate
+    fn normalize_suffix(suffix: &str) -> Result<Option<String>, &'static str> {
+        if !suffix.chars().all(|char| char.is_ascii_alphanumeric() || "-._~/%!$&'()*+,;=:@".contains(char)) {
+            return Err("Expected the suffix to be a path of URL-safe characters");
+        }
+        let suffix = suffix.trim_end_matches('/');
+        Ok(if suffix.is_empty() { None } else { Some(String::from(suffix)) })
+    }
 }
 
-pub async fn clean_candidates(file_path: &str) -> io::Result<()> {
-    let candidates = get_candidates(file_path).await?;
-    println!("Loaded candidates: {}", candidates.len());
-    let mut candidates = remove_duplicates(candidates);
-    candidates.sort();
-    println!("Unique candidates: {}", candidates.len());
-    let s = candidates.into_iter()
-        .map(|candidate| candidate.to_string())
+/// Candidates under the `#` comment lines preceding them
+type Section = (Vec<String>, Vec<TrackerCandidate>);
+
+/// What `clean_candidates` and `merge_candidates` read and kept
+#[derive(Clone, Debug, Default)]
+pub struct CleanSummary {
+    pub loaded: usize,
+    /// Lines which weren't candidates, and were dropped
+    pub unparseable: usize,
+    pub unique: usize,
+    /// In the order they were found, each one after the candidate it duplicates
+    pub duplicates: Vec<TrackerCandidate>,
+}
+
+/// Comment lines act as section headers: candidates are deduplicated across the whole file,
+/// but each one is sorted within the section it was found in, right below its header
+pub async fn clean_candidates(file_path: &str) -> io::Result<CleanSummary> {
+    let contents = tokio::fs::read_to_string(file_path).await?;
+    let mut sections = Vec::new();
+    let (loaded, unparseable) = read_sections(&contents, &mut sections);
+    write_sections(file_path, sections, loaded, unparseable).await
+}
+
+/// Like `clean_candidates`, but concatenates the sections of every input into `output_path`.
+/// A candidate listed in several inputs is kept in the section it first appears in
+pub async fn merge_candidates(input_paths: &[String], output_path: &str) -> io::Result<CleanSummary> {
+    let mut sections = Vec::new();
+    let mut loaded = 0;
+    let mut unparseable = 0;
+    for input_path in input_paths {
+        let (file_loaded, file_unparseable) = read_sections(&read_input(input_path).await?, &mut sections);
+        loaded += file_loaded;
+        unparseable += file_unparseable;
+    }
+    write_sections(output_path, sections, loaded, unparseable).await
+}
+
+/// Drops the lines of the `failed` candidates, and returns how many went. Comments, unparseable lines and
+/// candidates that weren't checked are kept as they are. The original is copied to `<file_path>.bak` first
+pub async fn prune_candidates(file_path: &str, failed: &HashSet<TrackerCandidate>) -> io::Result<usize> {
+    let contents = tokio::fs::read_to_string(file_path).await?;
+    atomic_file::write(format!("{}.bak", file_path), &contents).await?;
+    let lines = contents.split('\n').collect::<Vec<_>>();
+    let kept = lines.iter()
+        .filter(|line| line.trim().starts_with('#')
+            || TrackerCandidate::from_string(line.trim()).map_or(true, |candidate| !failed.contains(&candidate)))
+        .copied()
+        .collect::<Vec<_>>();
+    atomic_file::write(file_path, kept.join("\n")).await?;
+    Ok(lines.len() - kept.len())
+}
+
+/// Appends the sections of `contents`, the first one starting afresh, and returns the loaded and unparseable line counts
+fn read_sections(contents: &str, sections: &mut Vec<Section>) -> (usize, usize) {
+    sections.push((Vec::new(), Vec::new()));
+    let mut loaded = 0;
+    let mut unparseable = 0;
+    for line in contents.split('\n').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        if line.starts_with('#') {
+            if !sections.last().expect("At least one section").1.is_empty() {
+                sections.push((Vec::new(), Vec::new()));
+            }
+            sections.last_mut().expect("At least one section").0.push(String::from(line));
+        } else if let Ok(candidate) = TrackerCandidate::from_string(line) {
+            sections.last_mut().expect("At least one section").1.push(candidate);
+            loaded += 1;
+        } else {
+            unparseable += 1;
+        }
+    }
+    (loaded, unparseable)
+}
+
+async fn write_sections(file_path: &str, sections: Vec<Section>, loaded: usize, unparseable: usize) -> io::Result<CleanSummary> {
+    let mut seen = HashSet::with_capacity(loaded);
+    let mut unique = 0;
+    let mut duplicates = Vec::new();
+    let s = sections.into_iter()
+        .flat_map(|(comments, candidates)| {
+            let (mut candidates, dropped) = remove_duplicates(candidates, &mut seen);
+            candidates.sort();
+            unique += candidates.len();
+            duplicates.extend(dropped);
+            comments.into_iter().chain(candidates.into_iter().map(|candidate| candidate.to_line()))
+        })
         .reduce(|a, b| format!("{}\n{}", a, b))
         .unwrap_or(String::from(""));
-    tokio::fs::write(file_path, s).await
+    atomic_file::write(file_path, s).await?;
+    Ok(CleanSummary { loaded, unparseable, unique, duplicates })
 }
 
 pub async fn get_candidates(file_path: &str) -> io::Result<Vec<TrackerCandidate>> {
-    Ok(tokio::fs::read_to_string(file_path).await?
+    Ok(get_candidates_verbose(file_path).await?
+        .into_iter()
+        .filter_map(|(_, candidate)| candidate.ok())
+        .collect::<Vec<_>>())
+}
+
+/// Like `get_candidates`, but keeps the 1-based line number and parse error of every
+/// line which is neither blank nor a `#` comment
+pub async fn get_candidates_verbose(file_path: &str) -> io::Result<Vec<(usize, Result<TrackerCandidate, &'static str>)>> {
+    Ok(read_input(file_path).await?
         .split('\n')
-        .map(|s| s.trim())
-        .filter(|s| !s.starts_with('#'))
-        .filter_map(|string| TrackerCandidate::from_string(string).ok())
+        .enumerate()
+        .map(|(index, s)| (index + 1, s.trim()))
+        .filter(|(_, s)| !s.is_empty() && !s.starts_with('#'))
+        .map(|(line_number, string)| (line_number, TrackerCandidate::from_string(string)))
         .collect::<Vec<_>>())
 }
 
-fn remove_duplicates(candidates: Vec<TrackerCandidate>) -> Vec<TrackerCandidate> {
-    let mut set = HashSet::with_capacity(candidates.len());
-    candidates.into_iter().for_each(|candidate| {
-        set.insert(candidate);
-    });
-    set.into_iter().collect()
-}
\ No newline at end of file
+/// `-` reads stdin to the end. Without tokio's io-util there's no async read of it,
+/// so the blocking read is moved off the runtime thread instead
+async fn read_input(file_path: &str) -> io::Result<String> {
+    if file_path != STDIN_PATH {
+        return tokio::fs::read_to_string(file_path).await;
+    }
+    tokio::task::spawn_blocking(|| std::io::read_to_string(std::io::stdin())).await
+        .map_err(io::Error::other)?
+}
+
+/// Returns the candidates not yet in `seen`, in their original order, and the ones that were
+fn remove_duplicates(
+    candidates: Vec<TrackerCandidate>,
+    seen: &mut HashSet<TrackerCandidate>
+) -> (Vec<TrackerCandidate>, Vec<TrackerCandidate>) {
+    candidates.into_iter().partition(|candidate| seen.insert(candidate.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(url: &str) -> (TransportType, String, u16, Option<String>) {
+        let candidate = TrackerCandidate::from_url(url).unwrap();
+        (candidate.transport_type, candidate.host, candidate.port, candidate.suffix)
+    }
+
+    fn announce() -> Option<String> {
+        Some(String::from("/announce"))
+    }
+
+    #[test]
+    fn parses_ipv4_hosts() {
+        assert_eq!(parts("udp://10.0.0.1:6969/announce"), (TransportType::UDP, String::from("10.0.0.1"), 6969, announce()));
+        assert_eq!(parts("udp://10.0.0.1:6969"), (TransportType::UDP, String::from("10.0.0.1"), 6969, None));
+    }
+
+    #[test]
+    fn parses_bracketed_ipv6_hosts() {
+        assert_eq!(parts("udp://[2001:db8::1]:6969/announce"), (TransportType::UDP, String::from("2001:db8::1"), 6969, announce()));
+        assert_eq!(parts("udp://[2001:db8::1]:6969"), (TransportType::UDP, String::from("2001:db8::1"), 6969, None));
+        assert_eq!(TrackerCandidate::from_url("udp://[2001:db8::1]:6969").unwrap().to_string(), "udp://[2001:db8::1]:6969");
+    }
+
+    #[test]
+    fn parses_hostnames() {
+        assert_eq!(parts("http://Tracker.Example.org:8080/announce"), (TransportType::HTTP, String::from("tracker.example.org"), 8080, announce()));
+        assert_eq!(parts("http://tracker.example.org:8080"), (TransportType::HTTP, String::from("tracker.example.org"), 8080, None));
+    }
+
+    #[test]
+    fn rejects_unbracketed_ipv6_hosts() {
+        assert!(TrackerCandidate::from_url("udp://2001:db8::1:6969/announce").is_err());
+        assert!(TrackerCandidate::from_url("udp://[2001:db8::1:6969/announce").is_err());
+    }
+
+    #[test]
+    fn defaults_the_port_of_each_transport() {
+        let cases = [
+            ("udp://tracker.example.org/announce", "udp://tracker.example.org:6969/announce"),
+            ("http://tracker.example.org/announce", "http://tracker.example.org:80/announce"),
+            ("https://tracker.example.org/announce", "https://tracker.example.org:443/announce"),
+        ];
+        for (url, expected) in cases.iter() {
+            assert_eq!(TrackerCandidate::from_url(url).unwrap().to_string(), *expected);
+        }
+    }
+
+    #[test]
+    fn keeps_an_explicit_port_of_each_transport() {
+        for url in ["udp://tracker.example.org:1337/announce", "http://tracker.example.org:8080/announce", "https://tracker.example.org:8443/announce"].iter() {
+            assert_eq!(TrackerCandidate::from_url(url).unwrap().to_string(), *url);
+        }
+    }
+
+    #[test]
+    fn dedups_candidates_differing_only_in_the_default_suffix() {
+        let candidates = ["udp://tracker.example.org:80", "udp://tracker.example.org:80/announce", "udp://tracker.example.org:80/announce/", "udp://tracker.example.org:80/other"].iter()
+            .map(|url| TrackerCandidate::from_url(url).unwrap())
+            .collect::<Vec<_>>();
+        let (kept, dropped) = remove_duplicates(candidates, &mut HashSet::new());
+        let kept = kept.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(kept, ["udp://tracker.example.org:80", "udp://tracker.example.org:80/other"]);
+        assert_eq!(dropped.len(), 2);
+    }
+
+    #[test]
+    fn keeps_http_candidates_differing_in_the_default_suffix() {
+        // HTTP trackers route on the path, a bare host is announced to at `/`
+        let bare = TrackerCandidate::from_url("http://tracker.example.org:80").unwrap();
+        assert_ne!(bare, TrackerCandidate::from_url("http://tracker.example.org:80/announce").unwrap());
+    }
+
+
+    #[test]
+    fn takes_bare_host_port_for_udp() {
+        assert_eq!(TrackerCandidate::from_string("tracker.example.org:6969").unwrap().to_string(), "udp://tracker.example.org:6969");
+        assert_eq!(TrackerCandidate::from_string("tracker.example.org:6969/announce").unwrap().to_string(), "udp://tracker.example.org:6969/announce");
+        assert_eq!(TrackerCandidate::from_string("[2001:db8::1]:6969").unwrap().to_string(), "udp://[2001:db8::1]:6969");
+    }
+
+    #[test]
+    fn rejects_bare_hosts_without_a_port() {
+        assert!(TrackerCandidate::from_string("tracker.example.org").is_err());
+        assert!(TrackerCandidate::from_string("tracker.example.org/announce").is_err());
+    }
+
+    #[test]
+    fn rejects_port_zero() {
+        assert_eq!(TrackerCandidate::from_url("udp://tracker.example.org:0/announce"), Err("Expected a non-zero port"));
+        assert_eq!(TrackerCandidate::from_string("tracker.example.org:0"), Err("Expected a non-zero port"));
+        assert!(TrackerCandidate::from_url("udp://tracker.example.org:65536/announce").is_err());
+    }
+
+    #[test]
+    fn parses_tags_from_the_comment() {
+        let candidate = TrackerCandidate::from_string("udp://tracker.example.org:6969/announce # region=eu tier=1").unwrap();
+        assert_eq!(candidate.tags.get("region").map(String::as_str), Some("eu"));
+        assert_eq!(candidate.tags.get("tier").map(String::as_str), Some("1"));
+        assert_eq!(candidate.comment.as_deref(), Some("region=eu tier=1"));
+    }
+
+    #[test]
+    fn keeps_comments_which_arent_tags() {
+        let candidate = TrackerCandidate::from_string("udp://tracker.example.org:6969/announce # EU tracker").unwrap();
+        assert!(candidate.tags.is_empty());
+        assert_eq!(candidate.to_line(), "udp://tracker.example.org:6969/announce # EU tracker");
+        let candidate = TrackerCandidate::from_string("udp://tracker.example.org:6969/announce # region=eu fast one").unwrap();
+        assert_eq!(candidate.to_line(), "udp://tracker.example.org:6969/announce # region=eu fast one");
+        let candidate = TrackerCandidate::from_string("udp://tracker.example.org:6969/announce # fast one tier=1 region=eu").unwrap();
+        assert_eq!(candidate.to_line(), "udp://tracker.example.org:6969/announce # fast one tier=1 region=eu");
+    }
+
+    #[test]
+    fn splits_at_the_first_comment_marker() {
+        let candidate = TrackerCandidate::from_string("udp://tracker.example.org:6969\t# a # b").unwrap();
+        assert_eq!(candidate.to_string(), "udp://tracker.example.org:6969");
+        assert_eq!(candidate.comment.as_deref(), Some("a # b"));
+        let candidate = TrackerCandidate::from_string("udp://tracker.example.org:6969 # a\t# b").unwrap();
+        assert_eq!(candidate.comment.as_deref(), Some("a\t# b"));
+    }
+
+    #[tokio::test]
+    async fn summarises_what_clean_dropped() {
+        let path = std::env::temp_dir().join(format!("candidates-clean-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let contents = "# EU\nudp://b.example.org:6969/announce\nnot a tracker\nudp://a.example.org:6969/announce\nudp://b.example.org:6969/announce\n";
+        tokio::fs::write(path, contents).await.unwrap();
+        let summary = clean_candidates(path).await.unwrap();
+        let cleaned = tokio::fs::read_to_string(path).await.unwrap();
+        tokio::fs::remove_file(path).await.unwrap();
+        assert_eq!((summary.loaded, summary.unparseable, summary.unique), (3, 1, 2));
+        assert_eq!(summary.duplicates.iter().map(ToString::to_string).collect::<Vec<_>>(), vec!["udp://b.example.org:6969/announce"]);
+        assert_eq!(cleaned, "# EU\nudp://a.example.org:6969/announce\nudp://b.example.org:6969/announce");
+    }
+}
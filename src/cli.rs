@@ -0,0 +1,436 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use bip_util::bt::InfoHash;
+use bip_utracker::announce::{AnnounceEvent, DesiredPeers};
+use log::LevelFilter;
+use always_online_torrent_trackers::candidates::STDIN_PATH;
+use always_online_torrent_trackers::socks5::Socks5Proxy;
+use always_online_torrent_trackers::tracker_check::{AddressFamily, AddressPolicy, CheckOptions, CompactMode, Probe};
+use always_online_torrent_trackers::tracker_client::{ANNOUNCE_HEADER_SIZE, MAX_NUM_WANT};
+use crate::scoring::Weights;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// The newline-delimited `*_hosts.txt` and `*_ipv4s.txt`/`*_ipv6s.txt` files
+    Text,
+    /// A single `results.json` covering successes and failures
+    Json,
+    /// `results.jsonl`, one JSON object per candidate, appended as soon as its result is final
+    JsonLines,
+}
+
+/// Order of the entries in the `*_hosts.txt` files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    Random,
+    /// Fastest trackers first
+    Rtt,
+    Alpha,
+    /// Best `scoring::score` first, blending RTT with the uptime in `--history`
+    Score,
+}
+
+/// Printed by `-h`/`--help`
+pub const USAGE: &str = "\
+Checks which BitTorrent trackers are online and writes the working ones out.
+
+Usage: always-online-torrent-trackers [options]
+       always-online-torrent-trackers merge --input <path>... --output <path> [-v]
+
+Input:
+  --input <path>                 Candidates file, - for stdin [candidates.txt]
+  --include <text>               Only check hosts containing this, repeatable
+  --exclude <text>               Skip hosts containing this, repeatable
+  --limit <n>                    Only check this many candidates, 0 for all [all]
+  --shuffle-input                Check the candidates in random order
+  --append-announce              Announce to /announce on the HTTP(S) candidates without a path
+  --clean                        Dedup, sort and rewrite the candidates file, then exit, -v lists the duplicates
+  --prune                        Remove the candidates which failed from the input file
+
+Checking:
+  --concurrency <n>              Checks running at once [10]
+  --per-host-concurrency <n>     Checks of the same host running at once [unlimited]
+  --rate <n>                     Checks started per second [unlimited]
+  --timeout-ms <n>               Timeout of each request [5000]
+  --dns-timeout-ms <n>           Timeout of each DNS lookup [3000]
+  --candidate-timeout-ms <n>     Time budget of one candidate, retries included [none]
+  --deadline <secs>              Time budget of the whole run [none]
+  --retries <n>                  Passes re-checking the candidates which timed out [0]
+  --verify-retries <n>           Re-probes of an address which timed out [1]
+  --rtt-samples <n>              Announces per address, the median RTT is kept [1]
+  --probe announce|scrape        Request sent to the trackers [announce]
+  --announce-event started|stopped|completed|none
+                                 Event of the probing announce [started]
+  --num-want <n>                 Peers asked for [tracker default]
+  --info-hash <hex>              40 hex digit info hash to announce [built-in]
+  --compact only|both            HTTP only, retry without compact=1 on rejection with both [only]
+  --family ipv4|ipv6|both        Address families checked [both]
+  --require-all-addresses        Every resolved address must answer [default]
+  --require-any-address          One answering address is enough
+  --alive-on-error               Count an ERROR response as alive
+  --max-response-bytes <n>       UDP only, drop larger announce responses [none]
+  --proxy socks5://<host:port>   SOCKS5 proxy the UDP checks go through
+  --bind <ip>                    Local address the probe sockets bind to
+
+Output:
+  --output-dir <path>            Where the output files go [.]
+  --format text|json|jsonl       Host lists, results.json or results.jsonl [text]
+  --hosts-out <path>             UDP hosts file [udp_hosts.txt]
+  --ipv4-out <path>              UDP IPv4 addresses file [udp_ipv4s.txt]
+  --ipv6-out <path>              UDP IPv6 addresses file [udp_ipv6s.txt]
+  --no-ip-output                 Don't write the address files
+  --combined-out <path>          Also write every tracker with its addresses
+  --fastest-ip-only              Only list the fastest address of each tracker
+  --annotate-ip                  Follow each address with the hosts behind it
+  --sort random|rtt|alpha|score  Order of the hosts files [random]
+  --rtt-weight <n>               Exponent of the RTT in --sort score [1]
+  --uptime-weight <n>            Exponent of the uptime in --sort score [1]
+  --seed <n>                     Seed of the shuffles, for reproducible output
+  --count-only                   Only print the summary
+  --analyze                      Report the IPs several hosts share
+  --history <path>               JSON file accumulating every tracker's uptime
+  --min-uptime <0..1>            Leave out trackers below this success rate, needs --history
+  --metrics <path>               Prometheus textfile of the run's totals and RTTs
+  --compare <path>               results.json of an earlier run, list what changed since
+  --min-ok <n>                   Exit with status 3 below this many passing trackers, 0 never does [1]
+
+Other:
+  -q, --quiet                    Print nothing but the summary
+  -v, --verbose                  Log every check's result, twice for every datagram
+  -vv                            Log every datagram
+  --self-test                    Check mock trackers on 127.0.0.1, then exit
+  -h, --help                     Print this and exit
+";
+
+/// Command line options. Every option defaults to the behaviour of a bare `cargo run`
+pub struct Config {
+    /// `-` for stdin
+    pub input: String,
+    pub output_dir: PathBuf,
+    pub concurrency: usize,
+    /// Most checks started per second, on top of the `concurrency` limit
+    pub rate: Option<f64>,
+    /// Most checks of the same host running at once, on top of the `concurrency` limit
+    pub per_host_concurrency: Option<usize>,
+    /// Only check this many candidates, counted after filtering out the ones we can't check
+    pub limit: Option<usize>,
+    /// Check the candidates in random order instead of the input's
+    pub shuffle_input: bool,
+    /// Lowercased host substrings. With any `include`, only hosts matching one of them are checked
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Give the HTTP(S) candidates listed without a path `DEFAULT_SUFFIX`, rather than announcing to `/`
+    pub append_announce: bool,
+    pub check_options: CheckOptions,
+    /// Extra passes re-checking the candidates which timed out, fully or partially
+    pub retries: usize,
+    /// Upper bound on the time spent checking, retries included
+    pub deadline: Option<Duration>,
+    /// Fewer trackers passing than this makes the run exit with a failure status, 0 turns the check off
+    pub min_ok: usize,
+    pub format: OutputFormat,
+    /// The text outputs, relative to `output_dir` unless absolute
+    pub hosts_out: PathBuf,
+    pub ipv4_out: PathBuf,
+    pub ipv6_out: PathBuf,
+    /// Write the IPv4 and IPv6 address files
+    pub ip_output: bool,
+    /// Every passing tracker along with its addresses, in one file
+    pub combined_out: Option<PathBuf>,
+    /// Only print the summary, leaving every output file, the history and the metrics alone
+    pub count_only: bool,
+    pub sort: SortOrder,
+    pub score_weights: Weights,
+    /// Seeds the shuffles of the input and output files, making them reproducible
+    pub seed: Option<u64>,
+    /// Only list the fastest address of each tracker in the IP outputs
+    pub fastest_ip_only: bool,
+    /// Suffix every line of the IP outputs with the tracker hosts behind that address
+    pub annotate_ip: bool,
+    /// Report the IPs several tracker hosts share after the run
+    pub analyze: bool,
+    /// JSON file accumulating every tracker's uptime across runs
+    pub history: Option<PathBuf>,
+    /// Prometheus textfile the run's totals and RTTs are written to
+    pub metrics: Option<PathBuf>,
+    /// `results.json` of an earlier run, the trackers which died or came back since are listed after the summary
+    pub compare: Option<PathBuf>,
+    /// Leave trackers below this historical success rate out of the hosts files
+    pub min_uptime: Option<f64>,
+    /// Remove the candidates which failed their check from the input file
+    pub prune: bool,
+    /// Only dedup, sort and rewrite the candidates file, without checking anything
+    pub clean: bool,
+    /// Set by `-q` (nothing but the summary), `-v`/`--verbose` (every check's result) and `-vv` (every datagram), `RUST_LOG` otherwise
+    pub log_level: Option<LevelFilter>,
+    /// Check mock trackers on 127.0.0.1 instead of the candidates
+    pub self_test: bool,
+    /// Print `USAGE` instead of doing anything else
+    pub help: bool,
+    /// Set by the `merge` subcommand, which replaces the check
+    pub merge: Option<Merge>,
+}
+
+/// `merge --input <path>... --output <path>`: combines candidate files the way `--clean` tidies one
+pub struct Merge {
+    pub inputs: Vec<String>,
+    pub output: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            input: String::from("candidates.txt"),
+            output_dir: PathBuf::from("."),
+            concurrency: 10,
+            rate: None,
+            per_host_concurrency: None,
+            limit: None,
+            shuffle_input: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            append_announce: false,
+            check_options: CheckOptions::default(),
+            retries: 0,
+            deadline: None,
+            min_ok: 1,
+            format: OutputFormat::Text,
+            hosts_out: PathBuf::from("udp_hosts.txt"),
+            ipv4_out: PathBuf::from("udp_ipv4s.txt"),
+            ipv6_out: PathBuf::from("udp_ipv6s.txt"),
+            ip_output: true,
+            combined_out: None,
+            count_only: false,
+            sort: SortOrder::Random,
+            score_weights: Weights::default(),
+            seed: None,
+            fastest_ip_only: false,
+            annotate_ip: false,
+            analyze: false,
+            history: None,
+            metrics: None,
+            compare: None,
+            min_uptime: None,
+            prune: false,
+            clean: false,
+            log_level: None,
+            self_test: false,
+            help: false,
+            merge: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Config, String> {
+        let args = args.collect::<Vec<_>>();
+        let mut config = Config::default();
+        // ahead of parsing, so help is printed whatever else is wrong with the arguments
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            return Ok(Config { help: true, ..config });
+        }
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("merge") {
+            args.next();
+            return Merge::from_args(args, &mut config.log_level).map(|merge| Config { merge: Some(merge), ..config });
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input" => config.input = require_value(&arg, args.next())?,
+                "--output-dir" => config.output_dir = PathBuf::from(require_value(&arg, args.next())?),
+                "--hosts-out" => config.hosts_out = PathBuf::from(require_value(&arg, args.next())?),
+                "--ipv4-out" => config.ipv4_out = PathBuf::from(require_value(&arg, args.next())?),
+                "--ipv6-out" => config.ipv6_out = PathBuf::from(require_value(&arg, args.next())?),
+                "--no-ip-output" => config.ip_output = false,
+                "--count-only" => config.count_only = true,
+                "--combined-out" => config.combined_out = Some(PathBuf::from(require_value(&arg, args.next())?)),
+                "--history" => config.history = Some(PathBuf::from(require_value(&arg, args.next())?)),
+                "--min-uptime" => config.min_uptime = Some(parse_fraction(&arg, args.next())?),
+                "--metrics" => config.metrics = Some(PathBuf::from(require_value(&arg, args.next())?)),
+                "--compare" => config.compare = Some(PathBuf::from(require_value(&arg, args.next())?)),
+                "--clean" => config.clean = true,
+                "--prune" => config.prune = true,
+                "-q" | "--quiet" => config.log_level = Some(LevelFilter::Off),
+                "-v" | "--verbose" => config.log_level = Some(match config.log_level {
+                    Some(LevelFilter::Debug) | Some(LevelFilter::Trace) => LevelFilter::Trace,
+                    _ => LevelFilter::Debug
+                }),
+                "-vv" => config.log_level = Some(LevelFilter::Trace),
+                "--self-test" => config.self_test = true,
+                "--annotate-ip" => config.annotate_ip = true,
+                "--fastest-ip-only" => config.fastest_ip_only = true,
+                "--analyze" => config.analyze = true,
+                "--limit" => config.limit = Some(parse_count(&arg, args.next())?).filter(|limit| *limit > 0),
+                "--shuffle-input" => config.shuffle_input = true,
+                "--append-announce" => config.append_announce = true,
+                "--include" => config.include.push(require_value(&arg, args.next())?.to_ascii_lowercase()),
+                "--exclude" => config.exclude.push(require_value(&arg, args.next())?.to_ascii_lowercase()),
+                "--concurrency" => config.concurrency = parse_positive(&arg, args.next())?,
+                "--rate" => config.rate = Some(parse_rate(&arg, args.next())?),
+                "--per-host-concurrency" => config.per_host_concurrency = Some(parse_positive(&arg, args.next())?),
+                "--min-ok" => config.min_ok = parse_count(&arg, args.next())?,
+                "--deadline" => config.deadline = Some(Duration::from_secs(parse_positive(&arg, args.next())? as u64)),
+                "--verify-retries" => config.check_options.verify_retries = parse_count(&arg, args.next())?,
+                "--rtt-samples" => config.check_options.rtt_samples = parse_positive(&arg, args.next())?,
+                "--retries" => config.retries = parse_count(&arg, args.next())?,
+                "--require-all-addresses" => config.check_options.address_policy = AddressPolicy::All,
+                "--require-any-address" => config.check_options.address_policy = AddressPolicy::Any,
+                "--alive-on-error" => config.check_options.alive_on_error = true,
+                "--max-response-bytes" => config.check_options.max_response_size = Some(match parse_count(&arg, args.next())? {
+                    size if size >= ANNOUNCE_HEADER_SIZE => size,
+                    _ => return Err(format!("Expected {} to be at least {}, the size of an empty announce response", arg, ANNOUNCE_HEADER_SIZE))
+                }),
+                "--family" => config.check_options.family = match require_value(&arg, args.next())?.as_str() {
+                    "ipv4" => AddressFamily::V4,
+                    "ipv6" => AddressFamily::V6,
+                    "both" => AddressFamily::Both,
+                    other => return Err(format!("Expected --family to be one of ipv4, ipv6, both, got '{}'", other))
+                },
+                "--info-hash" => config.check_options.info_hash = Some(parse_info_hash(&arg, args.next())?),
+                "--proxy" => config.check_options.proxy = Some(Socks5Proxy::from_string(&require_value(&arg, args.next())?)
+                    .map_err(|err| format!("Invalid {}: {}", arg, err))?),
+                "--bind" => config.check_options.bind = Some(match require_value(&arg, args.next())?.parse::<IpAddr>() {
+                    Ok(bind) => bind,
+                    Err(_) => return Err(format!("Expected {} to be an IP address", arg))
+                }),
+                "--dns-timeout-ms" => config.check_options.dns_timeout = Duration::from_millis(parse_positive(&arg, args.next())? as u64),
+                "--candidate-timeout-ms" => config.check_options.candidate_timeout = Some(Duration::from_millis(parse_positive(&arg, args.next())? as u64)),
+                "--timeout-ms" => config.check_options.timeout = Duration::from_millis(parse_positive(&arg, args.next())? as u64),
+                "--format" => config.format = match require_value(&arg, args.next())?.as_str() {
+                    "text" => OutputFormat::Text,
+                    "json" => OutputFormat::Json,
+                    "jsonl" => OutputFormat::JsonLines,
+                    other => return Err(format!("Expected --format to be one of text, json, jsonl, got '{}'", other))
+                },
+                "--probe" => config.check_options.probe = match require_value(&arg, args.next())?.as_str() {
+                    "announce" => Probe::Announce,
+                    "scrape" => Probe::Scrape,
+                    other => return Err(format!("Expected --probe to be one of announce, scrape, got '{}'", other))
+                },
+                "--compact" => config.check_options.compact = match require_value(&arg, args.next())?.as_str() {
+                    "only" => CompactMode::Only,
+                    "both" => CompactMode::Both,
+                    other => return Err(format!("Expected --compact to be one of only, both, got '{}'", other))
+                },
+                "--announce-event" => config.check_options.announce_event = match require_value(&arg, args.next())?.as_str() {
+                    "started" => AnnounceEvent::Started,
+                    "stopped" => AnnounceEvent::Stopped,
+                    "completed" => AnnounceEvent::Completed,
+                    "none" => AnnounceEvent::None,
+                    other => return Err(format!("Expected --announce-event to be one of started, stopped, completed, none, got '{}'", other))
+                },
+                "--num-want" => config.check_options.num_want = match parse_positive(&arg, args.next())? {
+                    num_want if num_want <= MAX_NUM_WANT => DesiredPeers::Specified(num_want as i32),
+                    _ => return Err(format!("Expected {} to be at most {}, the peers that fit in one UDP response", arg, MAX_NUM_WANT))
+                },
+                "--seed" => config.seed = Some(match require_value(&arg, args.next())?.parse::<u64>() {
+                    Ok(seed) => seed,
+                    Err(_) => return Err(format!("Expected {} to be a non-negative integer", arg))
+                }),
+                "--sort" => config.sort = match require_value(&arg, args.next())?.as_str() {
+                    "random" => SortOrder::Random,
+                    "rtt" => SortOrder::Rtt,
+                    "alpha" => SortOrder::Alpha,
+                    "score" => SortOrder::Score,
+                    other => return Err(format!("Expected --sort to be one of rtt, random, alpha, score, got '{}'", other))
+                },
+                "--rtt-weight" => config.score_weights.rtt = parse_weight(&arg, args.next())?,
+                "--uptime-weight" => config.score_weights.uptime = parse_weight(&arg, args.next())?,
+                _ => return Err(format!("Unknown argument '{}'", arg))
+            }
+        }
+        if config.clean && config.input == STDIN_PATH {
+            return Err(String::from("--clean rewrites the input file, so it can't read stdin"));
+        }
+        if config.prune && config.input == STDIN_PATH {
+            return Err(String::from("--prune rewrites the input file, so it can't read stdin"));
+        }
+        if config.prune && config.count_only {
+            return Err(String::from("--count-only writes nothing, so it can't --prune"));
+        }
+        if let Some(bind) = config.check_options.bind.filter(|bind| !config.check_options.family.includes(bind)) {
+            return Err(format!("--bind {} is of the address family --family leaves out", bind));
+        }
+        if config.min_uptime.is_some() && config.history.is_none() {
+            return Err(String::from("--min-uptime requires --history"));
+        }
+        Ok(config)
+    }
+}
+
+impl Merge {
+    fn from_args(mut args: impl Iterator<Item = String>, log_level: &mut Option<LevelFilter>) -> Result<Merge, String> {
+        let mut inputs = Vec::new();
+        let mut output = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input" => inputs.push(require_value(&arg, args.next())?),
+                "--output" => output = Some(require_value(&arg, args.next())?),
+                "-v" | "--verbose" => *log_level = Some(LevelFilter::Debug),
+                _ => return Err(format!("Unknown merge argument '{}'", arg))
+            }
+        }
+        if inputs.is_empty() {
+            return Err(String::from("merge requires at least one --input"));
+        }
+        let output = output.ok_or_else(|| String::from("merge requires an --output"))?;
+        if output == STDIN_PATH {
+            return Err(String::from("merge writes its --output to a file, not stdout"));
+        }
+        Ok(Merge { inputs, output })
+    }
+}
+
+fn require_value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for {}", flag))
+}
+
+fn parse_positive(flag: &str, value: Option<String>) -> Result<usize, String> {
+    let value = require_value(flag, value)?;
+    match value.parse::<usize>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err(format!("Expected {} to be a positive integer, got '{}'", flag, value))
+    }
+}
+
+fn parse_count(flag: &str, value: Option<String>) -> Result<usize, String> {
+    let value = require_value(flag, value)?;
+    value.parse::<usize>().map_err(|_| format!("Expected {} to be a non-negative integer, got '{}'", flag, value))
+}
+
+fn parse_fraction(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let value = require_value(flag, value)?;
+    match value.parse::<f64>() {
+        Ok(number) if (0.0..=1.0).contains(&number) => Ok(number),
+        _ => Err(format!("Expected {} to be a number between 0 and 1, got '{}'", flag, value))
+    }
+}
+
+fn parse_rate(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let value = require_value(flag, value)?;
+    match value.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("Expected {} to be a positive number, got '{}'", flag, value))
+    }
+}
+
+fn parse_weight(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let value = require_value(flag, value)?;
+    match value.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("Expected {} to be a non-negative number, got '{}'", flag, value))
+    }
+}
+
+fn parse_info_hash(flag: &str, value: Option<String>) -> Result<InfoHash, String> {
+    let value = require_value(flag, value)?;
+    if value.len() != 40 || !value.chars().all(|char| char.is_ascii_hexdigit()) {
+        return Err(format!("Expected {} to be 40 hex digits, got '{}'", flag, value));
+    }
+    let mut bytes = [0u8; 20];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[2 * index..2 * index + 2], 16).expect("Hex digits to parse");
+    }
+    Ok(InfoHash::from(bytes))
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io;
+
+use crate::atomic_file;
+use crate::json::{self, ToJson, Value};
+
+/// Below this many checks a tracker's success rate is too noisy to judge it by
+pub const MIN_SAMPLES: u32 = 5;
+
+/// Checks accumulated for one tracker over all the runs recorded in the history file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackerHistory {
+    pub checks: u32,
+    pub successes: u32,
+    /// Unix timestamp, in seconds, of the latest successful check
+    pub last_seen: Option<u64>,
+}
+
+impl TrackerHistory {
+    pub fn success_rate(&self) -> f64 {
+        if self.checks == 0 { return 0.0; }
+        self.successes as f64 / self.checks as f64
+    }
+
+    fn from_json(value: &Value) -> Option<TrackerHistory> {
+        Some(TrackerHistory {
+            checks: value.get("checks")?.as_f64()? as u32,
+            successes: value.get("successes")?.as_f64()? as u32,
+            last_seen: value.get("last_seen").and_then(Value::as_f64).map(|last_seen| last_seen as u64),
+        })
+    }
+}
+
+impl ToJson for TrackerHistory {
+    fn to_json(&self) -> Value {
+        let mut entries = vec![
+            ("checks", Value::from(self.checks)),
+            ("successes", Value::from(self.successes)),
+        ];
+        if let Some(last_seen) = self.last_seen {
+            entries.push(("last_seen", Value::Number(last_seen as f64)));
+        }
+        Value::object(entries)
+    }
+}
+
+/// Uptime of every tracker ever checked, keyed by the candidate's `to_string()`
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    pub trackers: BTreeMap<String, TrackerHistory>,
+}
+
+impl History {
+    /// A missing file is an empty history, the first run creates it
+    pub async fn load(path: &Path) -> io::Result<History> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(History::default()),
+            Err(err) => return Err(err)
+        };
+        let invalid = |err: &str| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err));
+        let entries = match json::parse(&contents).map_err(invalid)? {
+            Value::Object(entries) => entries,
+            _ => return Err(invalid("Expected a JSON object"))
+        };
+        let trackers = entries.iter()
+            .map(|(key, value)| TrackerHistory::from_json(value)
+                .map(|tracker| (key.clone(), tracker))
+                .ok_or_else(|| invalid("Expected checks and successes counts for every tracker")))
+            .collect::<io::Result<_>>()?;
+        Ok(History { trackers })
+    }
+
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        atomic_file::write(path, self.to_json().to_string()).await
+    }
+
+    pub fn record(&mut self, key: String, success: bool) {
+        let tracker = self.trackers.entry(key).or_default();
+        tracker.checks += 1;
+        if success {
+            tracker.successes += 1;
+            tracker.last_seen = Some(SystemTime::now().duration_since(UNIX_EPOCH)
+                .expect("System clock to be past the epoch")
+                .as_secs());
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&TrackerHistory> {
+        self.trackers.get(key)
+    }
+
+    /// Trackers with fewer than `MIN_SAMPLES` checks pass, pending more data
+    pub fn meets_uptime(&self, key: &str, min_uptime: f64) -> bool {
+        match self.get(key) {
+            Some(tracker) if tracker.checks >= MIN_SAMPLES => tracker.success_rate() >= min_uptime,
+            _ => true
+        }
+    }
+}
+
+impl ToJson for History {
+    fn to_json(&self) -> Value {
+        Value::Object(self.trackers.iter()
+            .map(|(key, tracker)| (key.clone(), tracker.to_json()))
+            .collect())
+    }
+}
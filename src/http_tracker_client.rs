@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::io::ErrorKind::{TimedOut, UnexpectedEof, WouldBlock};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use bip_utracker::announce::{AnnounceEvent, AnnounceRequest, DesiredPeers};
+use log::debug;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time;
+use tokio::time::error::Elapsed;
+use crate::bencode;
+use crate::candidates::TrackerCandidate;
+use self::HttpTrackerClientError::{ApplicationError, GeneralError, Redirect};
+
+/// Trackers answer with a handful of peers at most, anything beyond this is not a tracker response
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+pub struct HttpTrackerClient<'a> {
+    candidate: &'a TrackerCandidate,
+    tracker_addr: &'a SocketAddr,
+    timeout: Duration,
+    pool: Option<&'a ConnectionPool>,
+    compact: bool,
+}
+
+/// Idle keep-alive connections by tracker address, shared by all the HTTP checks of a run
+/// so the cleanup announce and other candidates on the same host skip the TCP handshake
+#[derive(Debug, Default)]
+pub struct ConnectionPool {
+    idle: Mutex<HashMap<SocketAddr, Vec<TcpStream>>>,
+    reused: AtomicUsize,
+}
+
+impl ConnectionPool {
+    pub fn reused(&self) -> usize {
+        self.reused.load(Ordering::Relaxed)
+    }
+
+    async fn checkout(&self, addr: &SocketAddr) -> Option<TcpStream> {
+        self.idle.lock().await.get_mut(addr).and_then(Vec::pop)
+    }
+
+    async fn checkin(&self, addr: SocketAddr, stream: TcpStream) {
+        self.idle.lock().await.entry(addr).or_default().push(stream);
+    }
+}
+
+/// How a tracker listed the `peers` of its response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeerEncoding {
+    /// BEP 23 packed addresses
+    Compact,
+    /// The original list of dictionaries with an `ip` and a `port` each
+    Dictionary,
+}
+
+pub struct AnnounceResponse {
+    pub interval: i64,
+    /// Re-announcing sooner than this gets a client throttled, few trackers send it
+    pub min_interval: Option<i64>,
+    /// `complete` and `incomplete`, which BEP 3 leaves optional
+    pub seeders: Option<i64>,
+    pub leechers: Option<i64>,
+    pub peers: Vec<SocketAddr>,
+    /// `None` when the response has no `peers` at all
+    pub peer_encoding: Option<PeerEncoding>,
+}
+
+impl<'a> HttpTrackerClient<'a> {
+    pub fn new(candidate: &'a TrackerCandidate, tracker_addr: &'a SocketAddr) -> Self {
+        Self {
+            candidate,
+            tracker_addr,
+            timeout: Duration::from_secs(5),
+            pool: None,
+            compact: true
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keeps the connection open after each response and hands it to `pool` for the next request to the same address
+    pub fn with_pool(mut self, pool: &'a ConnectionPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Asks for dictionary peers with `compact=0` instead. Trackers may answer in either form regardless
+    pub fn with_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub async fn announce(&self, announce_req: AnnounceRequest<'_>) -> HttpTrackerClientResult<AnnounceResponse> {
+        let request = self.build_announce_request(&announce_req);
+
+        let response = time::timeout(self.timeout, self.exchange(request.as_bytes())).await??;
+
+        let body = HttpTrackerClient::parse_http_response(&response)?;
+        let body = bencode::decode(&body).map_err(|err| ApplicationError(String::from(err)))?;
+
+        if let Some(failure_reason) = body.get("failure reason") {
+            return Err(ApplicationError(format!(
+                "Tracker responded with a failure reason: {}",
+                String::from_utf8_lossy(failure_reason.as_bytes().unwrap_or_default())
+            )));
+        }
+
+        let interval = body.get("interval")
+            .and_then(|interval| interval.as_int())
+            .ok_or_else(|| ApplicationError(String::from("ANNOUNCE response is missing the interval")))?;
+
+        let peer_encoding = match body.get("peers") {
+            Some(bencode::Value::Bytes(_)) => Some(PeerEncoding::Compact),
+            Some(bencode::Value::List(_)) => Some(PeerEncoding::Dictionary),
+            _ => None
+        };
+        let peers = body.get("peers")
+            .and_then(|peers| peers.as_bytes())
+            .map(HttpTrackerClient::parse_compact_v4)
+            .or_else(|| body.get("peers")
+                .and_then(|peers| peers.as_list())
+                .map(HttpTrackerClient::parse_dictionary_peers))
+            .unwrap_or_default()
+            .into_iter()
+            .chain(body.get("peers6")
+                .and_then(|peers| peers.as_bytes())
+                .map(HttpTrackerClient::parse_compact_v6)
+                .unwrap_or_default())
+            .collect();
+
+        Ok(AnnounceResponse {
+            interval,
+            min_interval: body.get("min interval").and_then(|min_interval| min_interval.as_int()),
+            seeders: body.get("complete").and_then(|complete| complete.as_int()),
+            leechers: body.get("incomplete").and_then(|incomplete| incomplete.as_int()),
+            peers,
+            peer_encoding
+        })
+    }
+
+    fn build_announce_request(&self, announce_req: &AnnounceRequest<'_>) -> String {
+        let path = self.candidate.suffix.as_deref().unwrap_or("/");
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let state = announce_req.state();
+        let event = match state.event() {
+            AnnounceEvent::Started => "&event=started",
+            AnnounceEvent::Stopped => "&event=stopped",
+            AnnounceEvent::Completed => "&event=completed",
+            AnnounceEvent::None => ""
+        };
+        let num_want = match announce_req.num_want() {
+            DesiredPeers::Specified(count) => format!("&numwant={}", count),
+            DesiredPeers::Default => String::new()
+        };
+
+        format!(
+            "GET {}{}info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact={}{}{} HTTP/1.1\r\n\
+            Host: {}\r\n\
+            Connection: {}\r\n\
+            \r\n",
+            path, separator,
+            HttpTrackerClient::url_encode(announce_req.info_hash().as_ref()),
+            HttpTrackerClient::url_encode(announce_req.peer_id().as_ref()),
+            announce_req.port(),
+            state.bytes_uploaded(),
+            state.bytes_downloaded(),
+            state.bytes_left(),
+            if self.compact { 1 } else { 0 },
+            event,
+            num_want,
+            self.candidate.authority(),
+            if self.pool.is_some() { "keep-alive" } else { "close" }
+        )
+    }
+
+    async fn exchange(&self, request: &[u8]) -> HttpTrackerClientResult<Vec<u8>> {
+        if let Some(pool) = self.pool {
+            if let Some(stream) = pool.checkout(self.tracker_addr).await {
+                match HttpTrackerClient::exchange_on(&stream, request).await {
+                    Ok((response, reusable)) => {
+                        pool.reused.fetch_add(1, Ordering::Relaxed);
+                        if reusable {
+                            pool.checkin(*self.tracker_addr, stream).await;
+                        }
+                        return Ok(response);
+                    }
+                    // the tracker may have closed the idle connection in the meantime, which only shows once it's used
+                    Err(err) => debug!("Reused connection to {} failed, reconnecting: {:?}", self.tracker_addr, err)
+                }
+            }
+        }
+
+        let stream = TcpStream::connect(self.tracker_addr).await?;
+        let (response, reusable) = HttpTrackerClient::exchange_on(&stream, request).await?;
+        if let (Some(pool), true) = (self.pool, reusable) {
+            pool.checkin(*self.tracker_addr, stream).await;
+        }
+        Ok(response)
+    }
+
+    /// Returns the response and whether the connection can carry another request
+    async fn exchange_on(stream: &TcpStream, request: &[u8]) -> HttpTrackerClientResult<(Vec<u8>, bool)> {
+        let mut written = 0;
+        while written < request.len() {
+            stream.writable().await?;
+            match stream.try_write(&request[written..]) {
+                Ok(count) => written += count,
+                Err(err) if err.kind() == WouldBlock => continue,
+                Err(err) => return Err(err.into())
+            }
+        }
+
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            stream.readable().await?;
+            match stream.try_read(&mut buffer) {
+                Ok(0) if response.is_empty() => return Err(io::Error::new(UnexpectedEof, "Connection closed before the response").into()),
+                Ok(0) => return Ok((response, false)),
+                Ok(count) => response.extend_from_slice(&buffer[..count]),
+                Err(err) if err.kind() == WouldBlock => continue,
+                Err(err) => return Err(err.into())
+            }
+            if response.len() > MAX_RESPONSE_SIZE {
+                return Err(GeneralError("HTTP response exceeds the maximum response size"));
+            }
+            if let Some(length) = HttpTrackerClient::framed_length(&response).map_err(GeneralError)? {
+                // anything past the framed response is not ours to answer, so the connection can't be reused
+                let reusable = length == response.len() && !HttpTrackerClient::closes_connection(&response);
+                response.truncate(length);
+                return Ok((response, reusable));
+            }
+        }
+    }
+
+    /// Length of the complete response once its body has been read in full, `None` while more is expected
+    /// or if only the end of the connection delimits it. Lengths too large to add up are an error
+    fn framed_length(response: &[u8]) -> Result<Option<usize>, &'static str> {
+        let header_end = match response.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(position) => position + 4,
+            None => return Ok(None)
+        };
+        let head = String::from_utf8_lossy(&response[..header_end]);
+        if HttpTrackerClient::is_chunked(&head) {
+            let mut offset = header_end;
+            loop {
+                let line_end = match response[offset..].windows(2).position(|window| window == b"\r\n") {
+                    Some(position) => offset + position,
+                    None => return Ok(None)
+                };
+                let size = match std::str::from_utf8(&response[offset..line_end]).ok()
+                    .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16).ok()) {
+                    Some(size) => size,
+                    None => return Ok(None)
+                };
+                offset = line_end + 2;
+                if size == 0 {
+                    // no trailers are expected, just the empty line closing the body
+                    return Ok(if response.len() >= offset + 2 { Some(offset + 2) } else { None });
+                }
+                offset = offset.checked_add(size)
+                    .and_then(|offset| offset.checked_add(2))
+                    .ok_or("Malformed HTTP chunk size")?;
+                if offset > response.len() {
+                    return Ok(None);
+                }
+            }
+        }
+        let content_length = match HttpTrackerClient::header_value(&head, "content-length").and_then(|value| value.parse::<usize>().ok()) {
+            Some(content_length) => content_length,
+            None => return Ok(None)
+        };
+        let length = header_end.checked_add(content_length).ok_or("Malformed HTTP Content-Length")?;
+        Ok(if response.len() >= length { Some(length) } else { None })
+    }
+
+    fn closes_connection(response: &[u8]) -> bool {
+        let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap_or(response.len());
+        let head = String::from_utf8_lossy(&response[..header_end]);
+        // HTTP/1.0 servers close unless asked otherwise, and we don't send the 1.0 keep-alive header
+        head.starts_with("HTTP/1.0") || HttpTrackerClient::header_value(&head, "connection")
+            .is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+
+    fn header_value<'h>(head: &'h str, name: &str) -> Option<&'h str> {
+        head.split("\r\n")
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .find(|(header, _)| header.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    }
+
+    fn is_chunked(head: &str) -> bool {
+        HttpTrackerClient::header_value(head, "transfer-encoding")
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"))
+    }
+
+    fn parse_http_response(response: &[u8]) -> HttpTrackerClientResult<Vec<u8>> {
+        let header_end = response.windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| ApplicationError(String::from("Incomplete HTTP response headers")))?;
+        let head = String::from_utf8_lossy(&response[..header_end]);
+        let body = &response[header_end + 4..];
+
+        let status = head.split("\r\n").next()
+            .and_then(|status_line| status_line.split_whitespace().nth(1))
+            .ok_or_else(|| ApplicationError(String::from("Malformed HTTP status line")))?;
+        if matches!(status, "301" | "302" | "303" | "307" | "308") {
+            if let Some(location) = HttpTrackerClient::header_value(&head, "location") {
+                return Err(Redirect(String::from(location)));
+            }
+        }
+        if status != "200" {
+            return Err(ApplicationError(format!("Expected HTTP status 200, got {}", status)));
+        }
+
+        if HttpTrackerClient::is_chunked(&head) {
+            HttpTrackerClient::decode_chunked(body)
+        } else {
+            Ok(body.to_vec())
+        }
+    }
+
+    fn decode_chunked(mut body: &[u8]) -> HttpTrackerClientResult<Vec<u8>> {
+        let mut decoded = Vec::new();
+        loop {
+            let line_end = body.windows(2)
+                .position(|window| window == b"\r\n")
+                .ok_or_else(|| ApplicationError(String::from("Incomplete chunked HTTP body")))?;
+            let size = std::str::from_utf8(&body[..line_end]).ok()
+                .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16).ok())
+                .ok_or_else(|| ApplicationError(String::from("Malformed HTTP chunk size")))?;
+            body = &body[line_end + 2..];
+            if size == 0 {
+                return Ok(decoded);
+            }
+            if body.len() < size {
+                return Err(ApplicationError(String::from("Incomplete chunked HTTP body")));
+            }
+            decoded.extend_from_slice(&body[..size]);
+            body = body.get(size + 2..).unwrap_or(&[]);
+        }
+    }
+
+    fn parse_compact_v4(peers: &[u8]) -> Vec<SocketAddr> {
+        peers.chunks_exact(6)
+            .map(|peer| SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::new(peer[0], peer[1], peer[2], peer[3]),
+                u16::from_be_bytes([peer[4], peer[5]])
+            )))
+            .collect()
+    }
+
+    /// Peers announcing a hostname instead of an IP are skipped
+    fn parse_dictionary_peers(peers: &[bencode::Value]) -> Vec<SocketAddr> {
+        peers.iter()
+            .filter_map(|peer| {
+                let ip = std::str::from_utf8(peer.get("ip")?.as_bytes()?).ok()?.parse::<IpAddr>().ok()?;
+                let port = u16::try_from(peer.get("port")?.as_int()?).ok()?;
+                Some(SocketAddr::new(ip, port))
+            })
+            .collect()
+    }
+
+    fn parse_compact_v6(peers: &[u8]) -> Vec<SocketAddr> {
+        peers.chunks_exact(18)
+            .map(|peer| {
+                let mut ip = [0u8; 16];
+                ip.copy_from_slice(&peer[..16]);
+                SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(ip),
+                    u16::from_be_bytes([peer[16], peer[17]]),
+                    0,
+                    0
+                ))
+            })
+            .collect()
+    }
+
+    fn url_encode(bytes: &[u8]) -> String {
+        bytes.iter()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (*byte as char).to_string(),
+                _ => format!("%{:02X}", byte)
+            })
+            .collect()
+    }
+}
+
+pub type HttpTrackerClientResult<T> = Result<T, HttpTrackerClientError>;
+
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum HttpTrackerClientError {
+    GeneralError(&'static str),
+    IoError(io::Error),
+    ApplicationError(String),
+    /// A 3xx response, with the `Location` it points to
+    Redirect(String)
+}
+
+impl From<io::Error> for HttpTrackerClientError {
+    fn from(err: io::Error) -> Self {
+        HttpTrackerClientError::IoError(err)
+    }
+}
+
+impl From<Elapsed> for HttpTrackerClientError {
+    fn from(_: Elapsed) -> Self {
+        HttpTrackerClientError::IoError(io::Error::new(TimedOut, ""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bip_util::bt::{InfoHash, PeerId};
+    use bip_utracker::announce::{ClientState, SourceIP};
+    use bip_utracker::option::AnnounceOptions;
+    use super::*;
+
+    fn announce_request() -> AnnounceRequest<'static> {
+        AnnounceRequest::new(
+            InfoHash::from([1u8; 20]),
+            PeerId::from([2u8; 20]),
+            ClientState::new(0, 100, 0, AnnounceEvent::Started),
+            SourceIP::ImpliedV4,
+            0,
+            DesiredPeers::Default,
+            6881,
+            AnnounceOptions::new()
+        )
+    }
+
+    #[test]
+    fn announces_to_the_root_without_a_path() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 80));
+        let bare = TrackerCandidate::from_url("http://tracker.example.org").unwrap();
+        assert!(HttpTrackerClient::new(&bare, &addr).build_announce_request(&announce_request()).starts_with("GET /?info_hash="));
+        let announce = TrackerCandidate::from_url("http://tracker.example.org/announce").unwrap();
+        assert!(HttpTrackerClient::new(&announce, &addr).build_announce_request(&announce_request()).starts_with("GET /announce?info_hash="));
+    }
+
+    #[test]
+    fn frames_by_content_length() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nde";
+        assert_eq!(HttpTrackerClient::framed_length(response), Ok(Some(response.len())));
+        assert_eq!(HttpTrackerClient::framed_length(&response[..response.len() - 1]), Ok(None));
+    }
+
+    #[test]
+    fn frames_chunked_bodies() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nde\r\n0\r\n\r\n";
+        assert_eq!(HttpTrackerClient::framed_length(response), Ok(Some(response.len())));
+    }
+
+    #[test]
+    fn rejects_lengths_which_overflow() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\nde";
+        assert!(HttpTrackerClient::framed_length(response).is_err());
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nde";
+        assert!(HttpTrackerClient::framed_length(response).is_err());
+    }
+}
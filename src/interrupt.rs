@@ -0,0 +1,30 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::time::{self, Duration};
+
+/// A stand-in for `tokio::signal::ctrl_c`, which needs tokio's signal feature:
+/// the handler only counts the Ctrl-Cs, and the runtime polls the count
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    // the first Ctrl-C lets the run write what it has, a second one doesn't wait for that
+    if INTERRUPTS.fetch_add(1, Ordering::SeqCst) > 0 {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+pub fn install() {
+    unsafe { libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+}
+
+pub fn requested() -> bool {
+    INTERRUPTS.load(Ordering::SeqCst) > 0
+}
+
+/// Resolves once Ctrl-C is pressed
+pub async fn wait() {
+    while !requested() {
+        time::sleep(POLL_INTERVAL).await;
+    }
+}
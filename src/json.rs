@@ -0,0 +1,299 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Arrays and objects nested deeper than this are rejected, a corrupted `--history` file mustn't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// A minimal JSON document model, just enough for our own output and sidecar files
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+impl Value {
+    pub fn object(entries: Vec<(&str, Value)>) -> Value {
+        Value::Object(entries.into_iter()
+            .map(|(key, value)| (String::from(key), value))
+            .collect())
+    }
+
+    /// Looks up `key` if this is an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(entries) => entries.iter()
+                .find(|(entry_key, _)| entry_key == key)
+                .map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(string: &str) -> Self {
+        Value::String(String::from(string))
+    }
+}
+
+impl From<String> for Value {
+    fn from(string: String) -> Self {
+        Value::String(string)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(number: u32) -> Self {
+        Value::Number(number as f64)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(bool) => write!(f, "{}", bool),
+            Value::Number(number) if !number.is_finite() => write!(f, "null"),
+            Value::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => write!(f, "{}", *number as i64),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(string) => write_string(f, string),
+            Value::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 { write!(f, ",")?; }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(entries) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 { write!(f, ",")?; }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for char in string.chars() {
+        match char {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            char if (char as u32) < 0x20 => write!(f, "\\u{:04x}", char as u32)?,
+            char => write!(f, "{}", char)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Parses a complete JSON document, anything but whitespace after the value is an error
+pub fn parse(input: &str) -> Result<Value, &'static str> {
+    let mut parser = Parser { chars: input.chars().peekable() };
+    let value = parser.parse_value(0)?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(_) => Err("Unexpected trailing characters")
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn parse_value(&mut self, depth: usize) -> Result<Value, &'static str> {
+        self.skip_whitespace();
+        if depth >= MAX_DEPTH && matches!(self.chars.peek(), Some('[') | Some('{')) {
+            return Err("JSON nested too deeply");
+        }
+        match self.chars.peek() {
+            Some('n') => self.parse_literal("null", Value::Null),
+            Some('t') => self.parse_literal("true", Value::Bool(true)),
+            Some('f') => self.parse_literal("false", Value::Bool(false)),
+            Some('"') => self.parse_string().map(Value::String),
+            Some('[') => self.parse_array(depth),
+            Some('{') => self.parse_object(depth),
+            Some(char) if *char == '-' || char.is_ascii_digit() => self.parse_number(),
+            Some(_) => Err("Unexpected character"),
+            None => Err("Unexpected end of input")
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Value) -> Result<Value, &'static str> {
+        for expected in literal.chars() {
+            if self.chars.next() != Some(expected) {
+                return Err("Invalid literal");
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<Value, &'static str> {
+        let mut number = String::new();
+        while let Some(char) = self.chars.peek() {
+            if !(char.is_ascii_digit() || matches!(char, '-' | '+' | '.' | 'e' | 'E')) {
+                break;
+            }
+            number.push(*char);
+            self.chars.next();
+        }
+        number.parse::<f64>().map(Value::Number).map_err(|_| "Invalid number")
+    }
+
+    fn parse_string(&mut self) -> Result<String, &'static str> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next().ok_or("Unterminated string")? {
+                '"' => return Ok(string),
+                '\\' => match self.chars.next().ok_or("Unterminated string")? {
+                    '"' => string.push('"'),
+                    '\\' => string.push('\\'),
+                    '/' => string.push('/'),
+                    'b' => string.push('\u{8}'),
+                    'f' => string.push('\u{c}'),
+                    'n' => string.push('\n'),
+                    'r' => string.push('\r'),
+                    't' => string.push('\t'),
+                    'u' => string.push(self.parse_unicode_escape()?),
+                    _ => return Err("Invalid escape sequence")
+                },
+                char => string.push(char)
+            }
+        }
+    }
+
+    /// The `\u` is already consumed, characters outside the BMP come as a surrogate pair
+    fn parse_unicode_escape(&mut self) -> Result<char, &'static str> {
+        let high = self.parse_hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or("Invalid unicode escape");
+        }
+        if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+            return Err("Unpaired surrogate");
+        }
+        let low = self.parse_hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err("Unpaired surrogate");
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or("Invalid unicode escape")
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, &'static str> {
+        (0..4).try_fold(0, |code, _| self.chars.next()
+            .and_then(|char| char.to_digit(16))
+            .map(|digit| code * 16 + digit)
+            .ok_or("Invalid unicode escape"))
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Value, &'static str> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err("Expected ',' or ']'")
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Value, &'static str> {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Value::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            entries.push((key, self.parse_value(depth + 1)?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(entries)),
+                _ => return Err("Expected ',' or '}'")
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), &'static str> {
+        match self.chars.next() {
+            Some(char) if char == expected => Ok(()),
+            _ => Err("Unexpected character")
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|char| char.is_ascii_whitespace()) {
+            self.chars.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_nesting_up_to_the_limit() {
+        let input = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&input).is_ok());
+        let input = format!("{}1{}", "{\"a\":".repeat(MAX_DEPTH), "}".repeat(MAX_DEPTH));
+        assert!(parse(&input).is_ok());
+    }
+
+    #[test]
+    fn rejects_deep_nesting_without_overflowing_the_stack() {
+        assert_eq!(parse(&"[".repeat(100_000)), Err("JSON nested too deeply"));
+        assert_eq!(parse(&"{\"a\":".repeat(100_000)), Err("JSON nested too deeply"));
+    }
+}
@@ -0,0 +1,20 @@
+//! Liveness checks for BitTorrent trackers, the same ones the binary runs to build the host lists.
+//!
+//! Parse a tracker URL with [`TrackerCandidate::from_string`] and hand it to [`check_candidate`]
+//! to get back a [`tracker_check::CandidateProfile`]
+
+pub mod atomic_file;
+pub mod batch;
+mod bencode;
+pub mod candidates;
+pub mod history;
+pub mod http_tracker_client;
+pub mod json;
+pub mod self_test;
+pub mod socks5;
+pub mod tracker_check;
+pub mod tracker_client;
+
+pub use batch::{check_candidates, ResultSink};
+pub use candidates::TrackerCandidate;
+pub use tracker_check::{check_candidate, check_udp_candidate};
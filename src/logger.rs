@@ -0,0 +1,30 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// A stand-in for `env_logger`: honours a plain `RUST_LOG=<level>` and writes to stderr,
+/// keeping stdout free for the run summary. `-q`, `-v` and `-vv` take precedence over `RUST_LOG`
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{:<5} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init(level: Option<LevelFilter>) {
+    let level = level
+        .or_else(|| std::env::var("RUST_LOG").ok().and_then(|level| level.parse::<LevelFilter>().ok()))
+        .unwrap_or(LevelFilter::Warn);
+    log::set_logger(&LOGGER).expect("Logger to be initialised only once");
+    log::set_max_level(level);
+}
+
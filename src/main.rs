@@ -1,98 +1,566 @@
-use std::collections::HashSet;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use log::{debug, warn};
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
 use tokio::io;
-use tokio::sync::Semaphore;
 use tokio::time::Instant;
-use crate::candidates::TransportType::UDP;
-use crate::tracker_check::CheckError;
+use always_online_torrent_trackers::{atomic_file, batch, candidates, self_test, tracker_check};
+use always_online_torrent_trackers::batch::{BatchOptions, CheckResult, ResultSink};
+use always_online_torrent_trackers::candidates::{CleanSummary, TrackerCandidate, TransportType};
+use always_online_torrent_trackers::candidates::TransportType::{HTTP, HTTPS, UDP};
+use always_online_torrent_trackers::history::History;
+use always_online_torrent_trackers::http_tracker_client::ConnectionPool;
+use always_online_torrent_trackers::json::Value;
+use always_online_torrent_trackers::tracker_check::{CandidateProfile, CheckError, CheckOptions, DnsCache};
+use crate::cli::{OutputFormat, SortOrder};
 
-mod candidates;
-mod tracker_check;
-mod tracker_client;
+mod analysis;
+mod cli;
+mod interrupt;
+mod logger;
+mod metrics;
+mod scoring;
+
+/// Exit status when fewer than `--min-ok` trackers passed. The output files are still written.
+/// The other statuses are 0 for a healthy run, 1 for an I/O error that aborted the run and 2 for invalid arguments
+const EXIT_TOO_FEW_OK: i32 = 3;
+
+/// Exit status when a `--self-test` scenario didn't come out as expected
+const EXIT_SELF_TEST_FAILED: i32 = 4;
+
+/// Exit status after Ctrl-C, once the results of the checks finished by then are written
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Passing trackers are appended here as they are found, and the file is removed once the real outputs are written
+const PARTIAL_HOSTS_FILE: &str = "partial_hosts.txt";
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> io::Result<()> {
-    let stream = candidates::get_candidates("candidates.txt").await?.into_iter();
-    let semaphore = Rc::new(Semaphore::new(10));
-    let profiles = stream
-        .filter(|candidate| candidate.transport_type == UDP)
-        .map(|candidate| {
-            let semaphore_local_ref = semaphore.clone();
-            async move {
-                let permit = semaphore_local_ref.acquire().await.expect("Semaphore to be operating");
-                let res = tracker_check::check_udp_candidate(candidate.clone()).await;
-                drop(permit);
-                match &res {
-                    Ok(profile) => { println!("Success: {:?}", profile) }
-                    Err(err) => { println!("Failure: {:?}", err) }
+    let config = match cli::Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\nRun with --help to list the options", err);
+            std::process::exit(2);
+        }
+    };
+    if config.help {
+        print!("{}", cli::USAGE);
+        return Ok(());
+    }
+    logger::init(config.log_level);
+    if config.self_test {
+        let mut all_passed = true;
+        for scenario in self_test::SCENARIOS.iter() {
+            match scenario.run().await? {
+                Ok(()) => println!("PASS {}", scenario.name),
+                Err(failure) => {
+                    println!("FAIL {}: {}", scenario.name, failure);
+                    all_passed = false;
                 }
-                res
             }
-        })
+        }
+        std::process::exit(if all_passed { 0 } else { EXIT_SELF_TEST_FAILED });
+    }
+    if let Some(merge) = &config.merge {
+        let summary = candidates::merge_candidates(&merge.inputs, &merge.output).await?;
+        print_clean_summary(&summary);
+        return Ok(());
+    }
+    if config.clean {
+        let summary = candidates::clean_candidates(&config.input).await?;
+        print_clean_summary(&summary);
+        return Ok(());
+    }
+    let mut rng: Box<dyn RngCore> = match config.seed {
+        Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+        None => Box::new(thread_rng())
+    };
+    let mut candidates = candidates::get_candidates_verbose(&config.input).await?.into_iter()
+        .filter_map(|(line_number, candidate)| candidate
+            .map_err(|err| warn!("Skipping {} line {}: {}", config.input, line_number, err))
+            .ok()
+            .map(|candidate| complete_http_path(candidate, line_number, &config)))
+        .filter(|candidate| config.include.is_empty()
+            || config.include.iter().any(|include| candidate.host.contains(include.as_str())))
+        .filter(|candidate| !config.exclude.iter().any(|exclude| candidate.host.contains(exclude.as_str())))
         .collect::<Vec<_>>();
+    let https = candidates.iter().filter(|candidate| candidate.transport_type == HTTPS).count();
+    if https > 0 {
+        warn!("Skipping {} HTTPS candidates: {}", https, tracker_check::HTTPS_UNSUPPORTED);
+        candidates.retain(|candidate| candidate.transport_type != HTTPS);
+    }
+    // shuffled before the limit applies, so `--limit` samples the whole list rather than its head
+    if config.shuffle_input {
+        candidates.shuffle(&mut rng);
+    }
+    candidates.truncate(config.limit.unwrap_or(usize::MAX));
+    // read before the checks, so a bad file fails fast and `--compare` can name this run's own results.json
+    let previous = match &config.compare {
+        Some(compare_path) => Some(tracker_check::load_results(compare_path).await?),
+        None => None
+    };
+    let dns_cache = Arc::new(DnsCache::default());
+    let http_pool = Arc::new(ConnectionPool::default());
+    let check_options = CheckOptions {
+        dns_cache: Some(dns_cache.clone()),
+        http_pool: Some(http_pool.clone()),
+        ..config.check_options.clone()
+    };
+    if !config.count_only {
+        tokio::fs::create_dir_all(&config.output_dir).await?;
+    }
+    interrupt::install();
+    // an interrupted run then still leaves the trackers found so far behind
+    let partial_path = config.output_dir.join(PARTIAL_HOSTS_FILE);
+    let mut sink = FileSink {
+        partial_hosts: if config.count_only { None } else { Some(std::fs::File::create(&partial_path)?) },
+        results_stream: if config.format == OutputFormat::JsonLines && !config.count_only {
+            Some(std::fs::File::create(config.output_dir.join("results.jsonl"))?)
+        } else {
+            None
+        },
+        hold_timeouts: config.retries > 0,
+    };
     let timestamp = Instant::now();
-    let profiles = futures::future::join_all(profiles).await;
+    let deadline = config.deadline.map(|deadline| timestamp + deadline);
+    let batch_options = BatchOptions {
+        concurrency: config.concurrency,
+        per_host_concurrency: config.per_host_concurrency,
+        rate: config.rate,
+        deadline
+    };
+    let mut profiles = batch::check_candidates(candidates.iter(), &batch_options, &check_options, interrupt::wait(), &mut sink).await?;
+    for pass in 1..=config.retries {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) || interrupt::requested() {
+            break;
+        }
+        let retried = profiles.iter()
+            .enumerate()
+            .filter(|(_, res)| is_timeout(res))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        if retried.is_empty() {
+            break;
+        }
+        debug!("Retry pass {}: re-checking {} candidates", pass, retried.len());
+        let retried_candidates = retried.iter().map(|index| &candidates[*index]);
+        let retried_profiles = batch::check_candidates(retried_candidates, &batch_options, &check_options, interrupt::wait(), &mut sink).await?;
+        // a retry called off by Ctrl-C leaves the timeout it was retrying in place
+        for (index, res) in retried.into_iter().zip(retried_profiles).filter(|(_, res)| !matches!(res, Err(CheckError::Cancelled))) {
+            profiles[index] = res;
+        }
+    }
+    sink.finish(&candidates, &profiles)?;
+    let cancelled = profiles.iter().filter(|res| matches!(res, Err(CheckError::Cancelled))).count();
+    if interrupt::requested() {
+        warn!("Interrupted, {} checks were cancelled. Writing the results of the ones finished so far", cancelled);
+    }
+
+    let lookups = dns_cache.hits() + dns_cache.misses();
+    if lookups > 0 {
+        debug!(
+            "DNS cache: {} hits out of {} lookups ({:.0}%)",
+            dns_cache.hits(), lookups, 100.0 * dns_cache.hits() as f64 / lookups as f64
+        );
+    }
+    if http_pool.reused() > 0 {
+        debug!("HTTP connections reused {} times", http_pool.reused());
+    }
     let mut all_ok = 0;
     let mut dns_unresolved = 0;
+    let mut dns_timeout = 0;
     let mut partial_timeout = 0;
+    let mut partial_timeout_fraction = 0.0;
     let mut complete_timeout = 0;
+    let mut no_self_peer = 0;
     let mut operational_error = 0;
+    let mut operational_causes = HashMap::new();
+    let mut oversized = 0;
+    let mut unreachable = 0;
     profiles.iter().for_each(|res| {
         match res {
             Ok(_) => { all_ok += 1; }
             Err(CheckError::DnsResolutionFailed) => { dns_unresolved += 1; }
-            Err(CheckError::PartialTimeout) => { partial_timeout += 1; }
+            Err(CheckError::DnsTimeout) => { dns_timeout += 1; }
+            Err(CheckError::PartialTimeout { timed_out, total, .. }) => {
+                partial_timeout += 1;
+                partial_timeout_fraction += *timed_out as f64 / *total as f64;
+            }
             Err(CheckError::Timeout) => { complete_timeout += 1; }
-            Err(CheckError::OperationalError) => { operational_error += 1; }
+            Err(CheckError::NoSelfPeer) => { no_self_peer += 1; }
+            Err(CheckError::OperationalError(cause)) => {
+                operational_error += 1;
+                *operational_causes.entry(cause.as_str()).or_insert(0) += 1;
+            }
+            Err(CheckError::OversizedResponse(_)) => { oversized += 1; }
+            Err(CheckError::Unreachable) => { unreachable += 1; }
+            Err(CheckError::Cancelled) => {}
         }
     });
+    let mut operational_causes = operational_causes.into_iter().collect::<Vec<_>>();
+    operational_causes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    for (cause, count) in operational_causes {
+        debug!("Operational error x{}: {}", count, cause);
+    }
+    profiles.iter()
+        .filter_map(|res| res.as_ref().ok())
+        .filter(|profile| profile.has_long_interval())
+        .for_each(|profile| warn!("{} asks for announces only every {}s", profile.candidate, profile.interval.unwrap_or_default()));
+    let partial_timeout_summary = if partial_timeout > 0 {
+        format!(" ({:.0}% of addresses on average)", 100.0 * partial_timeout_fraction / partial_timeout as f64)
+    } else {
+        String::new()
+    };
+    let mut rtts = profiles.iter()
+        .filter_map(|res| res.as_ref().ok())
+        .map(|profile| profile.rtt_ms)
+        .collect::<Vec<_>>();
+    rtts.sort_unstable_by(f64::total_cmp);
+    let rtt_summary = if rtts.is_empty() {
+        String::from("no RTT data")
+    } else {
+        format!(
+            "RTT min {:.1}ms , median {:.1}ms , p90 {:.1}ms , max {:.1}ms",
+            rtts[0], percentile(&rtts, 0.5), percentile(&rtts, 0.9), rtts[rtts.len() - 1]
+        )
+    };
     println!(
-        "OK {} , DNS failure {} , p/Timeout {} , Timeout {} , Operational error {}",
-        all_ok, dns_unresolved, partial_timeout, complete_timeout, operational_error
+        "OK {} , DNS failure {} , DNS timeout {} , p/Timeout {}{} , Timeout {} , Unreachable {} , No self peer {} , Operational error {} , Oversized {} , {}",
+        all_ok, dns_unresolved, dns_timeout, partial_timeout, partial_timeout_summary, complete_timeout, unreachable, no_self_peer, operational_error, oversized, rtt_summary
     );
 
-    let mut output_hosts = profiles.iter()
+    if config.analyze {
+        let successful = profiles.iter()
+            .filter_map(|res| res.as_ref().ok())
+            .collect::<Vec<_>>();
+        println!("{}", analysis::shared_ips_report(&successful));
+    }
+
+    match &previous {
+        // the checks cut short by Ctrl-C would show up as newly dead
+        Some(_) if interrupt::requested() => {}
+        Some(previous) => print_comparison(previous, &candidates, &profiles),
+        None => {}
+    }
+
+    if config.count_only {
+        println!("Finished in {:?}", timestamp.elapsed());
+        exit_if_too_few_ok(all_ok, config.min_ok);
+        return Ok(());
+    }
+
+    let history = match &config.history {
+        // the checks cut short by Ctrl-C would count against the trackers
+        Some(_) if interrupt::requested() => None,
+        Some(history_path) => {
+            let mut history = History::load(history_path).await?;
+            candidates.iter()
+                .zip(profiles.iter())
+                .for_each(|(candidate, res)| history.record(candidate.to_string(), res.is_ok()));
+            history.save(history_path).await?;
+            Some(history)
+        }
+        None => None
+    };
+
+    // likewise, a check cut short doesn't prove a tracker dead
+    if config.prune && !interrupt::requested() {
+        let failed = candidates.iter()
+            .zip(profiles.iter())
+            .filter(|(_, res)| res.is_err())
+            .map(|(candidate, _)| candidate.clone())
+            .collect::<HashSet<_>>();
+        let pruned = candidates::prune_candidates(&config.input, &failed).await?;
+        println!("Pruned {} failed candidates from {}, the original is in {}.bak", pruned, config.input, config.input);
+    }
+
+    if let Some(metrics_path) = &config.metrics {
+        let totals = [
+            ("ok", all_ok),
+            ("dns_failure", dns_unresolved),
+            ("dns_timeout", dns_timeout),
+            ("partial_timeout", partial_timeout),
+            ("timeout", complete_timeout),
+            ("unreachable", unreachable),
+            ("no_self_peer", no_self_peer),
+            ("operational_error", operational_error),
+            ("oversized_response", oversized),
+            ("cancelled", cancelled),
+        ];
+        let successful = profiles.iter()
+            .filter_map(|res| res.as_ref().ok())
+            .collect::<Vec<_>>();
+        atomic_file::write(metrics_path, metrics::render(&totals, &successful, timestamp.elapsed())).await?;
+    }
+
+    if config.format != OutputFormat::Text {
+        if config.format == OutputFormat::Json {
+            let results = candidates.iter()
+                .zip(profiles.iter())
+                .map(|(candidate, res)| tracker_check::result_to_json(candidate, res))
+                .collect::<Vec<_>>();
+            atomic_file::write(config.output_dir.join("results.json"), Value::Array(results).to_string()).await?;
+        }
+        tokio::fs::remove_file(&partial_path).await?;
+        println!("Finished in {:?}", timestamp.elapsed());
+        exit_if_too_few_ok(all_ok, config.min_ok);
+        return Ok(());
+    }
+
+    let successful = profiles.iter()
         .filter_map(|res| res.as_ref().ok())
-        .map(|profile| profile.candidate.clone())
         .collect::<Vec<_>>();
-    output_hosts.shuffle(&mut thread_rng());
-    let output_hosts = output_hosts.into_iter()
-        .map(|candidate| candidate.to_string())
+    let output_udp = hosts_output(&successful, &[UDP], &config, history.as_ref(), &mut rng);
+    atomic_file::write(config.output_dir.join(&config.hosts_out), output_udp).await?;
+    let output_http = hosts_output(&successful, &[HTTP], &config, history.as_ref(), &mut rng);
+    atomic_file::write(config.output_dir.join("http_hosts.txt"), output_http).await?;
+
+    if config.ip_output {
+        let output_ip4 = addrs_output(&successful, SocketAddr::is_ipv4, &config, &mut rng);
+        atomic_file::write(config.output_dir.join(&config.ipv4_out), output_ip4).await?;
+
+        let output_ip6 = addrs_output(&successful, SocketAddr::is_ipv6, &config, &mut rng);
+        atomic_file::write(config.output_dir.join(&config.ipv6_out), output_ip6).await?;
+    }
+
+    if let Some(combined_out) = &config.combined_out {
+        let output_combined = combined_output(&successful, &config, history.as_ref(), &mut rng);
+        atomic_file::write(config.output_dir.join(combined_out), output_combined).await?;
+    }
+
+    atomic_file::write(config.output_dir.join("failures.txt"), failures_output(&candidates, &profiles)).await?;
+    tokio::fs::remove_file(&partial_path).await?;
+
+    println!("Finished in {:?}", timestamp.elapsed());
+    exit_if_too_few_ok(all_ok, config.min_ok);
+    Ok(())
+}
+
+/// HTTP trackers route on the path, which is almost always `/announce` but which lists leave out now and then
+fn complete_http_path(mut candidate: TrackerCandidate, line_number: usize, config: &cli::Config) -> TrackerCandidate {
+    if candidate.transport_type == UDP || candidate.suffix.is_some() {
+        return candidate;
+    }
+    if config.append_announce {
+        candidate.suffix = Some(String::from(candidates::DEFAULT_SUFFIX));
+    } else {
+        warn!("{} line {}: {} has no path and is announced to at /, --append-announce makes it {}",
+            config.input, line_number, candidate, candidates::DEFAULT_SUFFIX);
+    }
+    candidate
+}
+
+/// The dropped duplicates are only logged, for `-v`
+fn print_clean_summary(summary: &CleanSummary) {
+    println!("Loaded candidates: {}", summary.loaded);
+    println!("Unparseable lines dropped: {}", summary.unparseable);
+    println!("Unique candidates: {}", summary.unique);
+    summary.duplicates.iter().for_each(|candidate| debug!("Duplicate dropped: {}", candidate));
+}
+
+/// Lists the candidates checked in both runs which passed in only one of them
+fn print_comparison(previous: &[(TrackerCandidate, CheckResult)], candidates: &[TrackerCandidate], profiles: &[CheckResult]) {
+    let previously_ok = previous.iter()
+        .map(|(candidate, res)| (candidate.to_string(), res.is_ok()))
+        .collect::<HashMap<_, _>>();
+    let (mut newly_dead, mut newly_alive) = (Vec::new(), Vec::new());
+    for (candidate, res) in candidates.iter().zip(profiles.iter()) {
+        match previously_ok.get(&candidate.to_string()) {
+            Some(true) if res.is_err() => newly_dead.push(candidate),
+            Some(false) if res.is_ok() => newly_alive.push(candidate),
+            _ => {}
+        }
+    }
+    println!("Newly dead {} , Newly alive {}", newly_dead.len(), newly_alive.len());
+    newly_dead.iter().for_each(|candidate| println!("- {}", candidate));
+    newly_alive.iter().for_each(|candidate| println!("+ {}", candidate));
+}
+
+fn exit_if_too_few_ok(ok: usize, min_ok: usize) {
+    if interrupt::requested() {
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+    if ok < min_ok {
+        eprintln!("Only {} trackers passed, expected at least {}", ok, min_ok);
+        std::process::exit(EXIT_TOO_FEW_OK);
+    }
+}
+
+/// Appends the passing trackers to the partial hosts file and, with `--format jsonl`, every result to `results.jsonl`
+struct FileSink {
+    partial_hosts: Option<std::fs::File>,
+    results_stream: Option<std::fs::File>,
+    /// A retry pass may still overturn a timeout, so those are only streamed by `finish`
+    hold_timeouts: bool,
+}
+
+impl ResultSink for FileSink {
+    fn accept(&mut self, candidate: &TrackerCandidate, res: &CheckResult) -> io::Result<()> {
+        let streamed_now = !self.hold_timeouts || !is_timeout(res);
+        if let Some(results_stream) = self.results_stream.as_mut().filter(|_| streamed_now) {
+            writeln!(results_stream, "{}", tracker_check::result_to_json(candidate, res))?;
+        }
+        match (res, &mut self.partial_hosts) {
+            (Ok(profile), Some(partial_hosts)) => writeln!(partial_hosts, "{}", profile.endpoint()),
+            _ => Ok(())
+        }
+    }
+}
+
+impl FileSink {
+    /// Streams the timeouts held back, once the retries are over
+    fn finish(&mut self, candidates: &[TrackerCandidate], profiles: &[CheckResult]) -> io::Result<()> {
+        let held_back = self.hold_timeouts;
+        if let Some(results_stream) = self.results_stream.as_mut().filter(|_| held_back) {
+            for (candidate, res) in candidates.iter().zip(profiles.iter()).filter(|(_, res)| is_timeout(res)) {
+                writeln!(results_stream, "{}", tracker_check::result_to_json(candidate, res))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The results a retry pass re-checks
+fn is_timeout(res: &CheckResult) -> bool {
+    matches!(res, Err(CheckError::Timeout) | Err(CheckError::PartialTimeout { .. }))
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn hosts_output(
+    profiles: &[&CandidateProfile],
+    transport_types: &[TransportType],
+    config: &cli::Config,
+    history: Option<&History>,
+    rng: &mut dyn RngCore
+) -> String {
+    let profiles = profiles.iter()
+        .filter(|profile| transport_types.contains(&profile.endpoint().transport_type))
+        .copied()
+        .collect();
+    // candidates redirecting to the same tracker are listed once
+    let mut listed = HashSet::new();
+    listed_profiles(profiles, config, history, rng).into_iter()
+        .filter(|profile| listed.insert(profile.endpoint().clone()))
+        .map(|profile| profile.endpoint().to_string())
         .reduce(|a, b| format!("{}\n{}", a, b))
-        .unwrap_or(String::from(""));
-    tokio::fs::write("udp_hosts.txt", output_hosts).await?;
+        .unwrap_or_default()
+}
 
-    let output_ip4 = profiles.iter()
-        .filter_map(|res| res.as_ref().ok())
-        .flat_map(|profile| profile.addrs.clone().into_iter())
-        .filter(|addr| addr.is_ipv4())
-        .map(|addr| addr.to_string())
-        .collect::<HashSet<_>>();
-    let mut output_ip4 = output_ip4.into_iter()
-        .collect::<Vec<_>>();
-    output_ip4.shuffle(&mut thread_rng());
-    let output_ip4 = output_ip4.into_iter()
+/// Every passing tracker, whatever its transport, followed by the addresses that answered:
+/// `<url> # <addr>, <addr>`
+fn combined_output(
+    profiles: &[&CandidateProfile],
+    config: &cli::Config,
+    history: Option<&History>,
+    rng: &mut dyn RngCore
+) -> String {
+    let mut listed = HashSet::new();
+    listed_profiles(profiles.to_vec(), config, history, rng).into_iter()
+        .filter(|profile| listed.insert(profile.endpoint().clone()))
+        .map(|profile| {
+            let addrs = profile.addrs.iter().map(SocketAddr::to_string).collect::<Vec<_>>();
+            format!("{} # {}", profile.endpoint(), addrs.join(", "))
+        })
         .reduce(|a, b| format!("{}\n{}", a, b))
-        .unwrap_or(String::from(""));
-    tokio::fs::write("udp_ipv4s.txt", output_ip4).await?;
+        .unwrap_or_default()
+}
 
-    let output_ip6 = profiles.iter()
-        .filter_map(|res| res.as_ref().ok())
-        .flat_map(|profile| profile.addrs.clone().into_iter())
-        .filter(|addr| addr.is_ipv6())
-        .map(|addr| addr.to_string())
-        .collect::<HashSet<_>>();
-    let mut output_ip6 = output_ip6.into_iter()
+/// The profiles in `--sort` order. With `--min-uptime`, trackers below that historical success rate are left out
+fn listed_profiles<'a>(
+    mut profiles: Vec<&'a CandidateProfile>,
+    config: &cli::Config,
+    history: Option<&History>,
+    rng: &mut dyn RngCore
+) -> Vec<&'a CandidateProfile> {
+    match config.sort {
+        SortOrder::Random => profiles.shuffle(rng),
+        SortOrder::Rtt => profiles.sort_by(|a, b| a.rtt_ms.total_cmp(&b.rtt_ms)),
+        SortOrder::Alpha => profiles.sort_by(|a, b| a.candidate.cmp(&b.candidate)),
+        SortOrder::Score => {
+            let score = |profile: &CandidateProfile| scoring::score(
+                profile,
+                history.and_then(|history| history.get(&profile.candidate.to_string())),
+                config.score_weights
+            );
+            profiles.sort_by(|a, b| score(b).total_cmp(&score(a)).then(a.rtt_ms.total_cmp(&b.rtt_ms)));
+        }
+    }
+    if let Some((history, min_uptime)) = history.zip(config.min_uptime) {
+        profiles.retain(|profile| history.meets_uptime(&profile.candidate.to_string(), min_uptime));
+    }
+    profiles
+}
+
+/// One `<url> <CheckError kind>` line per failed candidate, sorted by URL
+/// Cancelled checks proved nothing either way, so they are left out
+fn failures_output(candidates: &[TrackerCandidate], profiles: &[CheckResult]) -> String {
+    let mut failures = candidates.iter()
+        .zip(profiles.iter())
+        .filter_map(|(candidate, res)| res.as_ref().err().map(|err| (candidate, err)))
+        .filter(|(_, err)| **err != CheckError::Cancelled)
         .collect::<Vec<_>>();
-    output_ip6.shuffle(&mut thread_rng());
-    let output_ip6 = output_ip6.into_iter()
+    failures.sort_by(|a, b| a.0.cmp(b.0));
+    failures.into_iter()
+        .map(|(candidate, err)| format!("{} {}", candidate, err.kind()))
         .reduce(|a, b| format!("{}\n{}", a, b))
-        .unwrap_or(String::from(""));
-    tokio::fs::write("udp_ipv6s.txt", output_ip6).await?;
+        .unwrap_or_default()
+}
 
-    println!("Finished in {:?}", timestamp.elapsed());
-    Ok(())
+/// An address shared by several trackers is written once, with `--annotate-ip` the line also names
+/// every tracker host it came from. `--sort rtt` puts the fastest addresses first, the other orders shuffle
+fn addrs_output(
+    profiles: &[&CandidateProfile],
+    family: fn(&SocketAddr) -> bool,
+    config: &cli::Config,
+    rng: &mut dyn RngCore
+) -> String {
+    let mut hosts_by_addr: HashMap<SocketAddr, (f64, Vec<&str>)> = HashMap::new();
+    profiles.iter()
+        .filter(|profile| profile.candidate.transport_type == UDP)
+        .flat_map(|profile| profile.addr_rtts().into_iter()
+            .filter(|(addr, _)| family(addr))
+            .take(if config.fastest_ip_only { 1 } else { usize::MAX })
+            .map(move |(addr, rtt_ms)| (addr, rtt_ms, profile.candidate.host.as_str())))
+        .for_each(|(addr, rtt_ms, host)| {
+            let (fastest_rtt_ms, hosts) = hosts_by_addr.entry(addr).or_insert((rtt_ms, Vec::new()));
+            *fastest_rtt_ms = fastest_rtt_ms.min(rtt_ms);
+            if !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        });
+    let line = |addr: &SocketAddr, hosts: &[&str]| if config.annotate_ip {
+        format!("{} # {}", addr, hosts.join(", "))
+    } else {
+        addr.to_string()
+    };
+    if config.sort != SortOrder::Rtt {
+        let lines = hosts_by_addr.iter().map(|(addr, (_, hosts))| line(addr, hosts)).collect();
+        return shuffled_lines(lines, rng);
+    }
+    let mut addrs = hosts_by_addr.into_iter().collect::<Vec<_>>();
+    addrs.sort_by(|(a_addr, (a_rtt_ms, _)), (b_addr, (b_rtt_ms, _))| a_rtt_ms.total_cmp(b_rtt_ms).then(a_addr.cmp(b_addr)));
+    addrs.into_iter()
+        .map(|(addr, (_, hosts))| line(&addr, &hosts))
+        .reduce(|a, b| format!("{}\n{}", a, b))
+        .unwrap_or_default()
+}
+
+/// Lazy/inconsiderate devs will tend to just grab the top-most entry,
+/// so we shuffle to avoid one host being spammed forever
+fn shuffled_lines(mut lines: Vec<String>, rng: &mut dyn RngCore) -> String {
+    // the lines come out of a HashMap, sorting first makes a seeded shuffle reproducible
+    lines.sort_unstable();
+    lines.shuffle(rng);
+    lines.into_iter()
+        .reduce(|a, b| format!("{}\n{}", a, b))
+        .unwrap_or_default()
 }
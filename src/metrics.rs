@@ -0,0 +1,32 @@
+use std::fmt::Write;
+use std::time::Duration;
+use always_online_torrent_trackers::tracker_check::CandidateProfile;
+
+/// Prometheus text exposition format, for node_exporter's textfile collector.
+/// `totals` are `(metric suffix, count)` pairs, each written as a `tracker_check_<suffix>_total` gauge
+pub fn render(totals: &[(&str, usize)], profiles: &[&CandidateProfile], run_duration: Duration) -> String {
+    let mut output = String::new();
+    for (suffix, count) in totals {
+        let name = format!("tracker_check_{}_total", suffix);
+        writeln!(output, "# TYPE {} gauge", name).expect("Writing to a String to succeed");
+        writeln!(output, "{} {}", name, count).expect("Writing to a String to succeed");
+    }
+
+    writeln!(output, "# TYPE tracker_check_rtt_ms gauge").expect("Writing to a String to succeed");
+    for profile in profiles {
+        writeln!(
+            output,
+            "tracker_check_rtt_ms{{tracker=\"{}\"}} {}",
+            escape_label_value(&profile.candidate.to_string()), profile.rtt_ms
+        ).expect("Writing to a String to succeed");
+    }
+
+    writeln!(output, "# TYPE tracker_check_duration_seconds gauge").expect("Writing to a String to succeed");
+    writeln!(output, "tracker_check_duration_seconds {}", run_duration.as_secs_f64()).expect("Writing to a String to succeed");
+    output
+}
+
+/// The exposition format only escapes backslashes, double quotes and line feeds in label values
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
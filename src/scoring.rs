@@ -0,0 +1,30 @@
+use always_online_torrent_trackers::history::TrackerHistory;
+use always_online_torrent_trackers::tracker_check::CandidateProfile;
+
+/// The RTT, in milliseconds, that halves a tracker's RTT score
+const RTT_REFERENCE_MS: f64 = 100.0;
+
+/// Exponents of the two factors of the score, 0 leaves a factor out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Weights {
+    pub rtt: f64,
+    pub uptime: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self { rtt: 1.0, uptime: 1.0 }
+    }
+}
+
+/// `rtt_score^rtt_weight * uptime^uptime_weight`, between 0 and 1, higher is better.
+///
+/// `rtt_score` is `100 / (100 + rtt_ms)`: 1 for an instant answer, 0.5 at 100 ms.
+/// `uptime` is the smoothed success rate `(successes + 1) / (checks + 2)`, so a tracker without
+/// history counts as 0.5 and a handful of checks can't make it look perfect, or dead
+pub fn score(profile: &CandidateProfile, history: Option<&TrackerHistory>, weights: Weights) -> f64 {
+    let rtt_score = RTT_REFERENCE_MS / (RTT_REFERENCE_MS + profile.rtt_ms.max(0.0));
+    let (successes, checks) = history.map_or((0, 0), |history| (history.successes, history.checks));
+    let uptime = (successes as f64 + 1.0) / (checks as f64 + 2.0);
+    rtt_score.powf(weights.rtt) * uptime.powf(weights.uptime)
+}
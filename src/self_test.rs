@@ -0,0 +1,128 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io;
+use tokio::net::UdpSocket;
+use crate::candidates::{TrackerCandidate, TransportType};
+use crate::tracker_check::{self, CandidateProfile, CheckError, CheckOptions, Probe};
+
+const PROTOCOL_ID: u64 = 0x41727101980;
+const CONNECTION_ID: u64 = 4242;
+const ANNOUNCE_INTERVAL: u32 = 1800;
+
+/// Where a mock tracker deviates from a well-behaved BEP 15 one
+#[derive(Clone, Copy, PartialEq)]
+enum Quirk {
+    None,
+    /// Every response is preceded by one carrying a different transaction id
+    MismatchedTransactionId,
+    /// The first datagram is never answered
+    DropFirstDatagram,
+    /// Announces are answered without any peers
+    OmitSelfPeer,
+}
+
+/// A mock tracker and the result checking it must come out with
+pub struct Scenario {
+    pub name: &'static str,
+    quirk: Quirk,
+    probe: Probe,
+    rtt_samples: usize,
+    /// Given the result and the number of CONNECT requests the mock answered
+    expected: fn(&Result<CandidateProfile, CheckError>, usize) -> bool,
+}
+
+pub const SCENARIOS: [Scenario; 6] = [
+    Scenario { name: "announce", quirk: Quirk::None, probe: Probe::Announce, rtt_samples: 1, expected: |res, _| res.is_ok() },
+    Scenario { name: "scrape", quirk: Quirk::None, probe: Probe::Scrape, rtt_samples: 1, expected: |res, _| res.is_ok() },
+    Scenario { name: "mismatched transaction id", quirk: Quirk::MismatchedTransactionId, probe: Probe::Announce, rtt_samples: 1, expected: |res, _| res.is_ok() },
+    Scenario { name: "dropped first datagram", quirk: Quirk::DropFirstDatagram, probe: Probe::Announce, rtt_samples: 1, expected: |res, _| res.is_ok() },
+    Scenario { name: "no self peer", quirk: Quirk::OmitSelfPeer, probe: Probe::Announce, rtt_samples: 1, expected: |res, _| matches!(res, Err(CheckError::NoSelfPeer)) },
+    // three sampled announces and the Stopped one, all on the first connection
+    Scenario { name: "connection reuse", quirk: Quirk::None, probe: Probe::Announce, rtt_samples: 3, expected: |res, connects| res.is_ok() && connects == 1 },
+];
+
+impl Scenario {
+    /// Checks the scenario's mock tracker on 127.0.0.1 the way real ones are checked, socket path included.
+    /// `Err` describes how the result differed from the expected one
+    pub async fn run(&self) -> io::Result<Result<(), String>> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let port = socket.local_addr()?.port();
+        let connects = Arc::new(AtomicUsize::new(0));
+        let mock = tokio::spawn(serve(socket, self.quirk, connects.clone()));
+
+        let candidate = TrackerCandidate::new(TransportType::UDP, "127.0.0.1", port, None)
+            .expect("The mock tracker to be a valid candidate");
+        let options = CheckOptions {
+            timeout: Duration::from_millis(500),
+            probe: self.probe,
+            rtt_samples: self.rtt_samples,
+            ..CheckOptions::default()
+        };
+        let res = tracker_check::check_udp_candidate(candidate, &options).await;
+        mock.abort();
+
+        let connects = connects.load(Ordering::SeqCst);
+        Ok(if (self.expected)(&res, connects) {
+            Ok(())
+        } else {
+            Err(format!("{:?} after {} CONNECTs", res, connects))
+        })
+    }
+}
+
+async fn serve(socket: UdpSocket, quirk: Quirk, connects: Arc<AtomicUsize>) -> io::Result<()> {
+    let mut buffer = [0u8; 2048];
+    let mut dropped = false;
+    loop {
+        let (read, peer) = socket.recv_from(&mut buffer).await?;
+        if quirk == Quirk::DropFirstDatagram && !dropped {
+            dropped = true;
+            continue;
+        }
+        if read >= 12 && buffer[8..12] == 0u32.to_be_bytes() {
+            connects.fetch_add(1, Ordering::SeqCst);
+        }
+        if let Some(response) = respond(&buffer[..read], quirk) {
+            if quirk == Quirk::MismatchedTransactionId {
+                let mut mismatched = response.clone();
+                mismatched[4] ^= 0xff;
+                send(&socket, &mismatched, peer).await?;
+            }
+            send(&socket, &response, peer).await?;
+        }
+    }
+}
+
+async fn send(socket: &UdpSocket, datagram: &[u8], peer: SocketAddr) -> io::Result<()> {
+    socket.send_to(datagram, peer).await.map(|_| ())
+}
+
+/// Requests too short to be BEP 15 ones go unanswered
+fn respond(request: &[u8], quirk: Quirk) -> Option<Vec<u8>> {
+    if request.len() < 16 {
+        return None;
+    }
+    let action = u32::from_be_bytes([request[8], request[9], request[10], request[11]]);
+    let transaction_id = &request[12..16];
+    let mut response = action.to_be_bytes().to_vec();
+    response.extend_from_slice(transaction_id);
+    match action {
+        0 if request[..8] == PROTOCOL_ID.to_be_bytes() => response.extend_from_slice(&CONNECTION_ID.to_be_bytes()),
+        1 if request.len() >= 98 => {
+            // interval, leechers, seeders, then the announcer as the only peer
+            response.extend_from_slice(&ANNOUNCE_INTERVAL.to_be_bytes());
+            response.extend_from_slice(&0u32.to_be_bytes());
+            response.extend_from_slice(&1u32.to_be_bytes());
+            if quirk != Quirk::OmitSelfPeer {
+                response.extend_from_slice(&[127, 0, 0, 1]);
+                response.extend_from_slice(&request[96..98]);
+            }
+        }
+        // seeders, completed and leechers of every hash asked for
+        2 => (16..request.len()).step_by(20).for_each(|_| response.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0])),
+        _ => return None
+    }
+    Some(response)
+}
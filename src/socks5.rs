@@ -0,0 +1,161 @@
+use std::io;
+use std::io::ErrorKind::{InvalidData, Other, UnexpectedEof, WouldBlock};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::{lookup_host, TcpStream};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const UDP_ASSOCIATE: u8 = 3;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// A relay header addressing a domain name of the maximum length
+pub const MAX_HEADER_SIZE: usize = 4 + 1 + 255 + 2;
+
+/// A SOCKS5 proxy given as `socks5://host:port`. Only the unauthenticated method is supported
+#[derive(Clone, Debug, PartialEq)]
+pub struct Socks5Proxy {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Socks5Proxy {
+    pub fn from_string(string: &str) -> Result<Socks5Proxy, &'static str> {
+        let authority = string.strip_prefix("socks5://").ok_or("Expected a socks5:// proxy URL")?;
+        let authority = authority.strip_suffix('/').unwrap_or(authority);
+        let (host, port) = authority.rsplit_once(':').ok_or("Expected host:port in the proxy URL")?;
+        let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+        if host.is_empty() {
+            return Err("Expected host:port in the proxy URL");
+        }
+        let port = port.parse::<u16>().map_err(|_| "Invalid proxy port")?;
+        Ok(Socks5Proxy { host: String::from(host), port })
+    }
+
+    pub async fn resolve(&self) -> io::Result<SocketAddr> {
+        lookup_host((self.host.as_str(), self.port)).await?
+            .next()
+            .ok_or_else(|| io::Error::new(Other, "Proxy host did not resolve"))
+    }
+}
+
+/// The proxy ends the UDP association when its TCP control connection closes,
+/// so this has to be kept alive for as long as the relay is used
+pub struct UdpAssociation {
+    _control: TcpStream,
+    pub relay_addr: SocketAddr,
+}
+
+/// RFC 1928 `UDP ASSOCIATE`. We don't know up front which address the datagrams will come from,
+/// so the unspecified address is announced and the proxy accepts them from anywhere
+pub async fn udp_associate(proxy_addr: &SocketAddr) -> io::Result<UdpAssociation> {
+    let control = TcpStream::connect(proxy_addr).await?;
+
+    write_all(&control, &[VERSION, 1, NO_AUTHENTICATION]).await?;
+    let mut method = [0u8; 2];
+    read_exact(&control, &mut method).await?;
+    if method != [VERSION, NO_AUTHENTICATION] {
+        return Err(io::Error::new(Other, "Proxy requires an unsupported authentication method"));
+    }
+
+    let unspecified = match proxy_addr {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let mut request = vec![VERSION, UDP_ASSOCIATE, 0];
+    write_addr(&mut request, &unspecified);
+    write_all(&control, &request).await?;
+
+    let mut reply = [0u8; 4];
+    read_exact(&control, &mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(Other, format!("Proxy refused UDP ASSOCIATE with reply code {}", reply[1])));
+    }
+    let ip = match reply[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            read_exact(&control, &mut octets).await?;
+            IpAddr::from(octets)
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            read_exact(&control, &mut octets).await?;
+            IpAddr::from(octets)
+        }
+        _ => return Err(io::Error::new(InvalidData, "Unsupported relay address type in the proxy reply"))
+    };
+    let mut port = [0u8; 2];
+    read_exact(&control, &mut port).await?;
+
+    // proxies commonly answer with the unspecified address, meaning the relay lives on the proxy host
+    let ip = if ip.is_unspecified() { proxy_addr.ip() } else { ip };
+    Ok(UdpAssociation {
+        _control: control,
+        relay_addr: SocketAddr::new(ip, u16::from_be_bytes(port)),
+    })
+}
+
+/// Prefixes `payload` with the relay header addressing it to `target`
+pub fn wrap_datagram(target: &SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = vec![0, 0, 0];
+    write_addr(&mut datagram, target);
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Length of the relay header in front of a received datagram's payload, `None` if malformed.
+/// Fragmented datagrams are rejected, as RFC 1928 allows
+pub fn header_len(datagram: &[u8]) -> Option<usize> {
+    if datagram.len() < 4 || datagram[2] != 0 {
+        return None;
+    }
+    let len = match datagram[3] {
+        ATYP_IPV4 => 4 + 4 + 2,
+        ATYP_IPV6 => 4 + 16 + 2,
+        ATYP_DOMAIN => 4 + 1 + *datagram.get(4)? as usize + 2,
+        _ => return None
+    };
+    if len > datagram.len() { None } else { Some(len) }
+}
+
+fn write_addr(buffer: &mut Vec<u8>, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buffer.push(ATYP_IPV4);
+            buffer.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buffer.push(ATYP_IPV6);
+            buffer.extend_from_slice(&ip.octets());
+        }
+    }
+    buffer.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+async fn write_all(stream: &TcpStream, bytes: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+    while written < bytes.len() {
+        stream.writable().await?;
+        match stream.try_write(&bytes[written..]) {
+            Ok(count) => written += count,
+            Err(err) if err.kind() == WouldBlock => continue,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(())
+}
+
+async fn read_exact(stream: &TcpStream, buffer: &mut [u8]) -> io::Result<()> {
+    let mut read = 0;
+    while read < buffer.len() {
+        stream.readable().await?;
+        match stream.try_read(&mut buffer[read..]) {
+            Ok(0) => return Err(io::Error::new(UnexpectedEof, "Proxy closed the connection")),
+            Ok(count) => read += count,
+            Err(err) if err.kind() == WouldBlock => continue,
+            Err(err) => return Err(err)
+        }
+    }
+    Ok(())
+}
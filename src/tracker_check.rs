@@ -1,40 +1,87 @@
 use std::error::Error;
+use std::fmt;
 use std::io::ErrorKind;
-use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 use bip_util::bt::{InfoHash, PeerId};
-use bip_utracker::announce::{AnnounceEvent, AnnounceRequest, AnnounceResponse, ClientState, DesiredPeers, SourceIP};
-use bip_utracker::announce::SourceIP::ImpliedV4;
-use bip_utracker::contact::CompactPeers;
+use bip_utracker::announce::{AnnounceEvent, AnnounceRequest, ClientState, DesiredPeers, SourceIP};
 use bip_utracker::option::AnnounceOptions;
-use bip_utracker::request::{CONNECT_ID_PROTOCOL_ID, RequestType};
-use bip_utracker::request::RequestType::{Announce, Connect, Scrape};
-use bip_utracker::response::{ResponseType, TrackerResponse};
 use bip_utracker::scrape::ScrapeRequest;
-use nom::{AsBytes, IResult};
+use log::debug;
 use tokio::io;
 use tokio::net::{lookup_host, UdpSocket};
+use tokio::sync::Mutex;
+use tokio::time;
 
-use crate::candidates::TrackerCandidate;
+use crate::candidates::{TrackerCandidate, TransportType};
+use crate::http_tracker_client::{ConnectionPool, HttpTrackerClient, HttpTrackerClientError, PeerEncoding};
+use crate::json::{self, ToJson, Value};
+use crate::socks5::{self, Socks5Proxy, UdpAssociation};
 use crate::tracker_client::{UdpTrackerClient, UdpTrackerClientError};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// The Stopped announce is retried once, a lost datagram would otherwise leave a phantom peer on the tracker
+const CLEANUP_ATTEMPTS: u32 = 2;
+
+/// Announce intervals from this many seconds up are flagged, clients would rarely refresh their peers
+pub const LONG_INTERVAL_SECS: u32 = 3600;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum CheckError {
+    /// The check was called off before it finished, so it says nothing about the tracker
+    Cancelled,
+    /// The host doesn't exist or has no addresses
     DnsResolutionFailed,
-    OperationalError,
-    PartialTimeout,
+    /// The resolver didn't answer within `CheckOptions::dns_timeout`
+    DnsTimeout,
+    /// The UDP tracker answered the announce correctly, but left us off the returned peers.
+    /// Some trackers never return any peers, so this tells such quirky ones apart from broken ones
+    NoSelfPeer,
+    /// Anything else going wrong, with a description of the cause
+    OperationalError(String),
+    /// The UDP tracker answered with more peers or bytes than asked for, and the answer was dropped unread
+    OversizedResponse(String),
+    /// Some, but not all, of the resolved addresses timed out
+    PartialTimeout { ok: usize, timed_out: usize, total: usize },
     Timeout,
+    /// This host has no route to the address, typically an IPv6 one without global IPv6 connectivity.
+    /// Such addresses are skipped, so a candidate only fails with this when none of its addresses can be reached
+    Unreachable,
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::Cancelled => write!(f, "The check was cancelled before it finished"),
+            CheckError::DnsResolutionFailed => write!(f, "The tracker host did not resolve"),
+            CheckError::DnsTimeout => write!(f, "Resolving the tracker host timed out"),
+            CheckError::NoSelfPeer => write!(f, "The tracker answered without returning our peer"),
+            CheckError::OperationalError(cause) => write!(f, "The check failed: {}", cause),
+            CheckError::OversizedResponse(cause) => write!(f, "The tracker answered with more than asked for: {}", cause),
+            CheckError::PartialTimeout { ok, timed_out, total } =>
+                write!(f, "{} of {} addresses timed out, {} answered", timed_out, total, ok),
+            CheckError::Timeout => write!(f, "The tracker did not answer in time"),
+            CheckError::Unreachable => write!(f, "None of the tracker's addresses can be routed to"),
+        }
+    }
 }
 
+impl Error for CheckError {}
+
 impl From<io::Error> for CheckError {
     fn from(err: std::io::Error) -> Self {
         match err.kind() {
             ErrorKind::TimedOut => CheckError::Timeout,
-            _ => {
-                println!("Io Error {:?}", err);
-                CheckError::OperationalError
-            }
+            ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable | ErrorKind::AddrNotAvailable => {
+                debug!("Io Error {:?}", err);
+                CheckError::Unreachable
+            },
+            _ => CheckError::OperationalError(format!("I/O error: {}", err))
         }
     }
 }
@@ -43,124 +90,976 @@ impl From<UdpTrackerClientError> for CheckError {
     fn from(err: UdpTrackerClientError) -> Self {
         match err {
             UdpTrackerClientError::IoError(err) => CheckError::from(err),
-            UdpTrackerClientError::ApplicationError(err) => {
-                println!("Application error {:?}", err);
-                CheckError::OperationalError
-            },
-            UdpTrackerClientError::GeneralError(err) => {
-                println!("General error {:?}", err);
-                CheckError::OperationalError
-            }
+            UdpTrackerClientError::ApplicationError(err) => CheckError::OperationalError(err),
+            UdpTrackerClientError::OversizedResponse(cause) => CheckError::OversizedResponse(cause),
+            UdpTrackerClientError::ErrorResponse(message) => CheckError::OperationalError(format!("Got an ERROR response: {}", message)),
+            UdpTrackerClientError::GeneralError(err) => CheckError::OperationalError(String::from(err))
+        }
+    }
+}
+
+impl From<HttpTrackerClientError> for CheckError {
+    fn from(err: HttpTrackerClientError) -> Self {
+        match err {
+            HttpTrackerClientError::IoError(err) => CheckError::from(err),
+            HttpTrackerClientError::ApplicationError(err) => CheckError::OperationalError(err),
+            HttpTrackerClientError::GeneralError(err) => CheckError::OperationalError(String::from(err)),
+            HttpTrackerClientError::Redirect(location) => CheckError::OperationalError(format!("Redirect to {} not followed", location))
         }
     }
 }
 
-#[derive(Debug)]
+impl CheckError {
+    /// The variant's name, without the detail it carries
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CheckError::Cancelled => "Cancelled",
+            CheckError::DnsResolutionFailed => "DnsResolutionFailed",
+            CheckError::DnsTimeout => "DnsTimeout",
+            CheckError::NoSelfPeer => "NoSelfPeer",
+            CheckError::OperationalError(_) => "OperationalError",
+            CheckError::OversizedResponse(_) => "OversizedResponse",
+            CheckError::PartialTimeout { .. } => "PartialTimeout",
+            CheckError::Timeout => "Timeout",
+            CheckError::Unreachable => "Unreachable",
+        }
+    }
+
+    /// Reads back what `error_entries` wrote, from the object holding them
+    fn from_json(value: &Value) -> Option<CheckError> {
+        let count = |key| value.get(key).and_then(Value::as_f64).map(|count| count as usize);
+        let cause = || value.get("cause").and_then(Value::as_str).map(String::from);
+        Some(match value.get("error")?.as_str()? {
+            "Cancelled" => CheckError::Cancelled,
+            "DnsResolutionFailed" => CheckError::DnsResolutionFailed,
+            "DnsTimeout" => CheckError::DnsTimeout,
+            "NoSelfPeer" => CheckError::NoSelfPeer,
+            "OperationalError" => CheckError::OperationalError(cause()?),
+            "OversizedResponse" => CheckError::OversizedResponse(cause()?),
+            "PartialTimeout" => CheckError::PartialTimeout { ok: count("ok")?, timed_out: count("timed_out")?, total: count("total")? },
+            "Timeout" => CheckError::Timeout,
+            "Unreachable" => CheckError::Unreachable,
+            _ => return None
+        })
+    }
+}
+
+impl ToJson for CheckError {
+    fn to_json(&self) -> Value {
+        Value::from(self.kind())
+    }
+}
+
+/// Outcome of checking one of the resolved addresses of a candidate, `Ok` holds the RTT in ms
+#[derive(Clone, Debug)]
+pub struct AddressResult {
+    pub addr: SocketAddr,
+    pub result: Result<f64, CheckError>,
+}
+
+impl ToJson for AddressResult {
+    fn to_json(&self) -> Value {
+        match &self.result {
+            Ok(rtt_ms) => Value::object(vec![
+                ("addr", Value::from(self.addr.to_string())),
+                ("status", Value::from("ok")),
+                ("rtt_ms", Value::Number(*rtt_ms)),
+            ]),
+            Err(err) => Value::object(vec![
+                ("addr", Value::from(self.addr.to_string())),
+                ("status", Value::from("error")),
+            ].into_iter().chain(error_entries(err)).collect())
+        }
+    }
+}
+
+impl AddressResult {
+    fn from_json(value: &Value) -> Option<AddressResult> {
+        Some(AddressResult {
+            addr: value.get("addr")?.as_str()?.parse().ok()?,
+            result: match value.get("status")?.as_str()? {
+                "ok" => Ok(value.get("rtt_ms")?.as_f64()?),
+                _ => Err(CheckError::from_json(value)?)
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct CandidateProfile {
     pub candidate: TrackerCandidate,
     pub addrs: Vec<SocketAddr>,
-    pub rtt_ms: u32,
+    /// Average over the addresses that answered, to the microsecond
+    pub rtt_ms: f64,
+    pub address_results: Vec<AddressResult>,
+    /// The probed swarm as the first address that answered reported it, `None` where its response doesn't say
+    pub seeders: Option<u32>,
+    pub leechers: Option<u32>,
+    pub peer_count: Option<u32>,
+    /// Seconds the tracker asks clients to wait between announces, scrapes don't tell
+    pub interval: Option<u32>,
+    /// The `min interval` of HTTP trackers which send one, `interval` otherwise
+    pub min_interval: Option<u32>,
+    /// HTTP only, `Dictionary` despite our asking for compact peers first marks a tracker forcing the heavier form
+    pub peer_encoding: Option<PeerEncoding>,
+    /// With `CheckOptions::alive_on_error`, the message of the ERROR response that passed the check
+    pub tracker_error: Option<String>,
+    /// HTTP only, where the followed redirect led. The rest of the profile describes that tracker
+    pub redirected_to: Option<TrackerCandidate>,
 }
 
-pub async fn check_udp_candidate(candidate: TrackerCandidate) -> Result<CandidateProfile, CheckError> {
-    let addrs = lookup_host(format!("{}:{}", &candidate.host, &candidate.port)).await
-        .map_err(|err| CheckError::DnsResolutionFailed)?.collect::<Vec<_>>();
-    if addrs.len() == 0 { return Err(CheckError::DnsResolutionFailed); }
+impl CandidateProfile {
+    /// The tracker to announce to, `candidate` unless it redirected elsewhere
+    pub fn endpoint(&self) -> &TrackerCandidate {
+        self.redirected_to.as_ref().unwrap_or(&self.candidate)
+    }
 
-    let responses = addrs.iter().map(|address| async move {
-        let socket = tokio::net::UdpSocket::bind(match address {
-            SocketAddr::V4(_) => "0.0.0.0:0",
-            SocketAddr::V6(_) => "[::]:0",
-        }.parse::<std::net::SocketAddr>().unwrap()).await.unwrap();
+    pub fn has_long_interval(&self) -> bool {
+        self.interval.is_some_and(|interval| interval >= LONG_INTERVAL_SECS)
+    }
 
-        let mut client = UdpTrackerClient::new(&socket, address);
-        let timestamp = Instant::now();
-        client.connect().await?;
+    /// The addresses which answered with their RTT in ms, fastest first
+    pub fn addr_rtts(&self) -> Vec<(SocketAddr, f64)> {
+        let mut addr_rtts = self.address_results.iter()
+            .filter_map(|address_result| address_result.result.as_ref().ok().map(|rtt_ms| (address_result.addr, *rtt_ms)))
+            .collect::<Vec<_>>();
+        addr_rtts.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        addr_rtts
+    }
+}
+
+/// What one address answered the probe with
+#[derive(Clone)]
+struct ProbeResponse {
+    rtt: Duration,
+    seeders: Option<u32>,
+    leechers: Option<u32>,
+    peer_count: Option<u32>,
+    interval: Option<u32>,
+    min_interval: Option<u32>,
+    peer_encoding: Option<PeerEncoding>,
+    tracker_error: Option<String>,
+    /// HTTP only, the `Location` of a redirect, which carries nothing about the swarm
+    redirect: Option<String>,
+}
+
+impl ProbeResponse {
+    /// An ERROR response carries nothing about the swarm, only that the tracker processed the request
+    fn error_response(rtt: Duration, message: String) -> ProbeResponse {
+        ProbeResponse {
+            rtt,
+            seeders: None,
+            leechers: None,
+            peer_count: None,
+            interval: None,
+            min_interval: None,
+            peer_encoding: None,
+            tracker_error: Some(message),
+            redirect: None
+        }
+    }
+
+    fn redirect_response(rtt: Duration, location: String) -> ProbeResponse {
+        ProbeResponse {
+            rtt,
+            seeders: None,
+            leechers: None,
+            peer_count: None,
+            interval: None,
+            min_interval: None,
+            peer_encoding: None,
+            tracker_error: None,
+            redirect: Some(location)
+        }
+    }
+}
+
+impl ToJson for CandidateProfile {
+    fn to_json(&self) -> Value {
+        let mut entries = vec![
+            ("candidate", Value::from(self.candidate.to_string())),
+            ("status", Value::from("ok")),
+            ("addrs", Value::from(self.addrs.iter().map(|addr| addr.to_string()).collect::<Vec<_>>())),
+            ("rtt_ms", Value::Number(self.rtt_ms)),
+            ("addresses", Value::Array(self.address_results.iter().map(ToJson::to_json).collect())),
+        ];
+        entries.extend(tag_entries(&self.candidate));
+        let reported = [
+            ("seeders", self.seeders),
+            ("leechers", self.leechers),
+            ("peer_count", self.peer_count),
+            ("interval", self.interval),
+            ("min_interval", self.min_interval),
+        ];
+        entries.extend(reported.iter()
+            .filter_map(|(key, count)| count.map(|count| (*key, Value::from(count)))));
+        if let Some(peer_encoding) = self.peer_encoding {
+            entries.push(("peer_encoding", Value::from(match peer_encoding {
+                PeerEncoding::Compact => "compact",
+                PeerEncoding::Dictionary => "dictionary",
+            })));
+        }
+        if self.has_long_interval() {
+            entries.push(("long_interval", Value::Bool(true)));
+        }
+        if let Some(tracker_error) = &self.tracker_error {
+            entries.push(("tracker_error", Value::from(tracker_error.clone())));
+        }
+        if let Some(redirected_to) = &self.redirected_to {
+            entries.push(("redirected_to", Value::from(redirected_to.to_string())));
+        }
+        Value::object(entries)
+    }
+}
 
-        let info_hash = InfoHash::from_bytes("tracker_test".as_bytes());
-        let peer_id = PeerId::from_bytes("tracker".as_bytes());
-        let source_ip = match address {
-            SocketAddr::V4(_) => SourceIP::ImpliedV4,
-            SocketAddr::V6(_) => SourceIP::ImpliedV6
+impl CandidateProfile {
+    /// The inverse of `to_json`, `None` if anything it writes is missing or malformed
+    fn from_json(candidate: TrackerCandidate, value: &Value) -> Option<CandidateProfile> {
+        let reported = |key| value.get(key).and_then(Value::as_f64).map(|count| count as u32);
+        let addrs = match value.get("addrs")? {
+            Value::Array(addrs) => addrs.iter()
+                .map(|addr| addr.as_str()?.parse().ok())
+                .collect::<Option<Vec<_>>>()?,
+            _ => return None
+        };
+        let address_results = match value.get("addresses")? {
+            Value::Array(address_results) => address_results.iter()
+                .map(AddressResult::from_json)
+                .collect::<Option<Vec<_>>>()?,
+            _ => return None
         };
+        Some(CandidateProfile {
+            candidate,
+            addrs,
+            rtt_ms: value.get("rtt_ms")?.as_f64()?,
+            address_results,
+            seeders: reported("seeders"),
+            leechers: reported("leechers"),
+            peer_count: reported("peer_count"),
+            interval: reported("interval"),
+            min_interval: reported("min_interval"),
+            peer_encoding: match value.get("peer_encoding").and_then(Value::as_str) {
+                Some("compact") => Some(PeerEncoding::Compact),
+                Some("dictionary") => Some(PeerEncoding::Dictionary),
+                Some(_) => return None,
+                None => None
+            },
+            tracker_error: value.get("tracker_error").and_then(Value::as_str).map(String::from),
+            redirected_to: match value.get("redirected_to") {
+                Some(redirected_to) => Some(TrackerCandidate::from_url(redirected_to.as_str()?).ok()?),
+                None => None
+            },
+        })
+    }
+}
 
-        let local_port = socket.local_addr().expect("Bind to have succeeded");
+/// Failures carry no profile, so the candidate they belong to is passed in separately
+pub fn result_to_json(candidate: &TrackerCandidate, result: &Result<CandidateProfile, CheckError>) -> Value {
+    match result {
+        Ok(profile) => profile.to_json(),
+        Err(err) => Value::object(vec![
+            ("candidate", Value::from(candidate.to_string())),
+            ("status", Value::from("error")),
+        ].into_iter().chain(tag_entries(candidate)).chain(error_entries(err)).collect())
+    }
+}
 
-        let announce_request = AnnounceRequest::new(
-            info_hash,
-            peer_id,
-            ClientState::new(0, 100, 0, AnnounceEvent::Started),
-            source_ip,
-            0,
-            DesiredPeers::Default,
-            local_port.port(),
-            AnnounceOptions::new()
-        );
+/// The inverse of `result_to_json`
+pub fn result_from_json(value: &Value) -> Result<(TrackerCandidate, Result<CandidateProfile, CheckError>), &'static str> {
+    let mut candidate = TrackerCandidate::from_url(value.get("candidate")
+        .and_then(Value::as_str)
+        .ok_or("Expected a candidate URL")?)?;
+    if let Some(Value::Object(tags)) = value.get("tags") {
+        candidate.tags = tags.iter()
+            .map(|(key, tag)| tag.as_str().map(|tag| (key.clone(), String::from(tag))))
+            .collect::<Option<_>>()
+            .ok_or("Expected every tag to be a string")?;
+    }
+    let result = match value.get("status").and_then(Value::as_str) {
+        Some("ok") => Ok(CandidateProfile::from_json(candidate.clone(), value).ok_or("Malformed profile")?),
+        Some("error") => Err(CheckError::from_json(value).ok_or("Malformed error")?),
+        _ => return Err("Expected a status of ok or error")
+    };
+    Ok((candidate, result))
+}
+
+/// Reads the `results.json` of an earlier `--format json` run back in
+pub async fn load_results(path: &Path) -> io::Result<Vec<(TrackerCandidate, Result<CandidateProfile, CheckError>)>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    let invalid = |err: &str| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err));
+    match json::parse(&contents).map_err(invalid)? {
+        Value::Array(results) => results.iter()
+            .enumerate()
+            .map(|(index, result)| result_from_json(result).map_err(|err| invalid(&format!("result {}: {}", index, err))))
+            .collect(),
+        _ => Err(invalid("Expected a JSON array"))
+    }
+}
+
+/// Untagged candidates have no `tags` entry at all
+fn tag_entries(candidate: &TrackerCandidate) -> Vec<(&'static str, Value)> {
+    if candidate.tags.is_empty() {
+        return Vec::new();
+    }
+    vec![("tags", Value::Object(candidate.tags.iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.clone())))
+        .collect()))]
+}
+
+/// The error with whatever detail it carries
+fn error_entries(err: &CheckError) -> Vec<(&'static str, Value)> {
+    let mut entries = vec![("error", err.to_json())];
+    match err {
+        CheckError::PartialTimeout { ok, timed_out, total } => entries.extend(vec![
+            ("ok", Value::from(*ok as u32)),
+            ("timed_out", Value::from(*timed_out as u32)),
+            ("total", Value::from(*total as u32)),
+        ]),
+        CheckError::OperationalError(cause) | CheckError::OversizedResponse(cause) => entries.push(("cause", Value::from(cause.clone()))),
+        _ => {}
+    }
+    entries
+}
 
-        let announce_resp = client.announce(announce_request).await?;
+/// How many of a candidate's resolved addresses must answer for the candidate to pass
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressPolicy {
+    All,
+    /// The profile then only lists, and averages the RTT over, the addresses which answered
+    Any,
+}
+
+/// Which of a candidate's resolved addresses are checked, the others are left out as if they never resolved
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+    Both,
+}
+
+impl AddressFamily {
+    pub fn includes(&self, ip: &IpAddr) -> bool {
+        match self {
+            AddressFamily::V4 => ip.is_ipv4(),
+            AddressFamily::V6 => ip.is_ipv6(),
+            AddressFamily::Both => true,
+        }
+    }
+}
+
+/// How a UDP tracker is asked to prove it is alive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Probe {
+    /// Announce ourselves and expect to be among the returned peers, then announce Stopped to clean up
+    Announce,
+    /// Read-only, any well-formed SCRAPE response for the probe hash is enough
+    Scrape,
+}
+
+/// Which peer encodings an HTTP tracker is asked for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompactMode {
+    /// Only `compact=1`
+    Only,
+    /// `compact=1`, then `compact=0` again should the tracker reject the first announce
+    Both,
+}
+
+/// Knobs shared by the UDP and HTTP checks
+#[derive(Clone, Debug)]
+pub struct CheckOptions {
+    /// How long to wait for each response from the tracker
+    pub timeout: Duration,
+    /// How long to wait for the candidate's host to resolve, a stalled resolver would otherwise hang the check
+    pub dns_timeout: Duration,
+    /// UDP only, extra probes of the addresses which timed out before the candidate is failed
+    pub verify_retries: usize,
+    /// UDP only, announces per address over one connection, their median RTT is recorded. Only the first decides liveness
+    pub rtt_samples: usize,
+    /// UDP only, caps the time spent probing all of a candidate's addresses, verification included
+    pub candidate_timeout: Option<Duration>,
+    pub address_policy: AddressPolicy,
+    pub family: AddressFamily,
+    /// UDP only, HTTP checks always announce
+    pub probe: Probe,
+    /// Event the probe announces with. The cleanup announce is always Stopped, and a Stopped probe
+    /// leaves us off the returned peers, so UDP checks then only pass with an `info_hash`
+    pub announce_event: AnnounceEvent,
+    /// Peers the probe announce asks for
+    pub num_want: DesiredPeers,
+    /// HTTP only, UDP peers are always compact
+    pub compact: CompactMode,
+    /// Announce for a real torrent instead of the synthetic probe hash, for trackers that only
+    /// answer for torrents they know. Its swarm answers with other peers, so we don't expect to see ourselves
+    pub info_hash: Option<InfoHash>,
+    /// UDP announces go through this proxy's UDP relay, HTTP checks still connect directly
+    pub proxy: Option<Socks5Proxy>,
+    /// UDP only, a well-formed ERROR response, e.g. to our unregistered probe hash, passes the check.
+    /// The tracker processed the request, which is all liveness asks for
+    pub alive_on_error: bool,
+    /// UDP only, ANNOUNCE responses larger than this many bytes are dropped as `OversizedResponse`
+    pub max_response_size: Option<usize>,
+    /// UDP only, local address the probe sockets bind to. Addresses of the other family fail the check
+    pub bind: Option<IpAddr>,
+    /// Shared by all the checks of a run, so a host listed with several ports is resolved once
+    pub dns_cache: Option<Arc<DnsCache>>,
+    /// Keep-alive connections to HTTP trackers, reused across the checks of a run
+    pub http_pool: Option<Arc<ConnectionPool>>,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            dns_timeout: Duration::from_secs(3),
+            verify_retries: 1,
+            rtt_samples: 1,
+            candidate_timeout: None,
+            address_policy: AddressPolicy::All,
+            family: AddressFamily::Both,
+            probe: Probe::Announce,
+            announce_event: AnnounceEvent::Started,
+            num_want: DesiredPeers::Default,
+            compact: CompactMode::Only,
+            info_hash: None,
+            proxy: None,
+            alive_on_error: false,
+            max_response_size: None,
+            bind: None,
+            dns_cache: None,
+            http_pool: None,
+        }
+    }
+}
+
+/// Resolutions by host for the duration of one run. Timeouts are not cached, so a retry can still succeed
+#[derive(Debug, Default)]
+pub struct DnsCache {
+    /// Port 0 addresses, which keep the scope id of a link-local IPv6 host given with a zone id
+    entries: Mutex<HashMap<String, Result<Vec<SocketAddr>, CheckError>>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl DnsCache {
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
 
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// Why HTTPS candidates fail: there is no TLS implementation among our dependencies
+pub const HTTPS_UNSUPPORTED: &str = "HTTPS trackers can't be checked without TLS support";
+
+/// Checks the candidate with the UDP or the HTTP checker, as its transport calls for
+pub async fn check_candidate(candidate: TrackerCandidate, options: &CheckOptions) -> Result<CandidateProfile, CheckError> {
+    match candidate.transport_type {
+        TransportType::UDP => check_udp_candidate(candidate, options).await,
+        TransportType::HTTP => check_http_candidate(candidate, options).await,
+        TransportType::HTTPS => Err(CheckError::OperationalError(String::from(HTTPS_UNSUPPORTED))),
+    }
+}
+
+/// Addresses which time out are probed again, up to `CheckOptions::verify_retries` times with the timeout doubling
+/// each time, before they are given up on. A lost first datagram would otherwise fail the candidate
+pub async fn check_udp_candidate(candidate: TrackerCandidate, options: &CheckOptions) -> Result<CandidateProfile, CheckError> {
+    let addrs = resolve_candidate(&candidate, options).await?;
+    let budget = options.candidate_timeout.map(|candidate_timeout| time::Instant::now() + candidate_timeout);
+
+    let mut responses = futures::future::join_all(addrs.iter()
+        .map(|address| probe_udp_address_within(address, options, options.timeout, budget))).await;
+
+    let mut timeout = options.timeout;
+    for _ in 0..options.verify_retries {
+        if budget.is_some_and(|budget| time::Instant::now() >= budget) {
+            break;
+        }
+        // an operational error, or an answer that already satisfies the policy, settles the candidate either way
+        let settled = responses.iter()
+            .any(|response| matches!(response,
+                Err(CheckError::OperationalError(_)) | Err(CheckError::OversizedResponse(_)) | Err(CheckError::NoSelfPeer)))
+            || (options.address_policy == AddressPolicy::Any && responses.iter().any(Result::is_ok));
+        let timed_out = responses.iter()
+            .enumerate()
+            .filter(|(_, response)| matches!(response, Err(CheckError::Timeout)))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        if settled || timed_out.is_empty() {
+            break;
+        }
+        timeout *= 2;
+        debug!("Verifying {} timed out addresses of {} with a {:?} timeout", timed_out.len(), candidate, timeout);
+        let verified = futures::future::join_all(timed_out.iter()
+            .map(|index| probe_udp_address_within(&addrs[*index], options, timeout, budget))).await;
+        for (index, response) in timed_out.into_iter().zip(verified) {
+            responses[index] = response;
+        }
+    }
+
+    profile_from_responses(candidate, addrs, responses, options.address_policy)
+}
+
+/// Addresses still unanswered once the candidate's `budget` runs out are timed out, the answers already in are kept
+async fn probe_udp_address_within(
+    address: &SocketAddr,
+    options: &CheckOptions,
+    timeout: Duration,
+    budget: Option<time::Instant>
+) -> Result<ProbeResponse, CheckError> {
+    match budget {
+        Some(budget) => time::timeout_at(budget, probe_udp_address(address, options, timeout)).await
+            .unwrap_or(Err(CheckError::Timeout)),
+        None => probe_udp_address(address, options, timeout).await
+    }
+}
+
+async fn probe_udp_address(address: &SocketAddr, options: &CheckOptions, timeout: Duration) -> Result<ProbeResponse, CheckError> {
+    let association = match &options.proxy {
+        Some(proxy) => Some(associate(proxy, timeout).await?),
+        None => None
+    };
+    // the socket talks to the relay when proxied, so it has to match the relay's family
+    let peer_addr = association.as_ref().map_or(address, |association| &association.relay_addr);
+
+    // each address gets its own socket: responses are read with a plain `recv`,
+    // so concurrent clients sharing one socket would consume each other's datagrams
+    if let SocketAddr::V6(peer_addr) = peer_addr {
+        if peer_addr.ip().is_unicast_link_local() && peer_addr.scope_id() == 0 {
+            return Err(CheckError::OperationalError(format!(
+                "Link-local address {} has no zone id telling which interface to reach it on", peer_addr)));
+        }
+    }
+    let bind_addr = match (peer_addr, options.bind) {
+        // a link-local source only exists on the interface the tracker is reached through
+        (SocketAddr::V6(peer_addr), Some(IpAddr::V6(bind))) if bind.is_unicast_link_local() =>
+            SocketAddr::V6(SocketAddrV6::new(bind, 0, 0, peer_addr.scope_id())),
+        (_, Some(bind)) if bind.is_ipv4() == peer_addr.is_ipv4() => SocketAddr::new(bind, 0),
+        (_, Some(bind)) => return Err(CheckError::OperationalError(
+            format!("Bind address {} can't reach {} of the other address family", bind, peer_addr))),
+        (SocketAddr::V4(_), None) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        (SocketAddr::V6(_), None) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+
+    let mut client = UdpTrackerClient::new(&socket, address).with_timeout(timeout);
+    if let Some(association) = &association {
+        client = client.with_relay(association.relay_addr);
+    }
+    if let Some(max_response_size) = options.max_response_size {
+        client = client.with_max_response_size(max_response_size);
+    }
+    let timestamp = Instant::now();
+    let mut client = match client.connect().await {
+        Err(UdpTrackerClientError::ErrorResponse(message)) if options.alive_on_error =>
+            return Ok(ProbeResponse::error_response(timestamp.elapsed(), message)),
+        client => client?
+    };
+    let connect_rtt = timestamp.elapsed();
+
+    let info_hash = probe_info_hash(options);
+
+    if options.probe == Probe::Scrape {
+        let mut scrape_request = ScrapeRequest::new();
+        scrape_request.insert(info_hash);
+        let stats = match client.scrape(scrape_request).await {
+            Err(UdpTrackerClientError::ErrorResponse(message)) if options.alive_on_error =>
+                return Ok(ProbeResponse::error_response(timestamp.elapsed(), message)),
+            stats => stats?
+        };
         let rtt = timestamp.elapsed();
+        let (seeders, _, leechers) = stats.first().copied().unwrap_or((-1, -1, -1));
+        return Ok(ProbeResponse {
+            rtt,
+            seeders: u32::try_from(seeders).ok(),
+            leechers: u32::try_from(leechers).ok(),
+            peer_count: None,
+            interval: None,
+            min_interval: None,
+            peer_encoding: None,
+            tracker_error: None,
+            redirect: None
+        });
+    }
+
+    let peer_id = random_peer_id();
+    // the Stopped announce reuses the key, trackers validating it would otherwise ignore the cleanup
+    let key = random_key();
+    let source_ip = match address {
+        SocketAddr::V4(_) => SourceIP::ImpliedV4,
+        SocketAddr::V6(_) => SourceIP::ImpliedV6
+    };
+
+    let local_port = socket.local_addr()?;
+
+    let announce_request = AnnounceRequest::new(
+        info_hash,
+        peer_id,
+        ClientState::new(0, 100, 0, options.announce_event),
+        source_ip,
+        key,
+        options.num_want,
+        local_port.port(),
+        AnnounceOptions::new()
+    );
 
-        let is_local_peer_returned = announce_resp.peers.iter()
-            .find(|peer| local_port.port() == peer.port())
-            .is_some();
+    // an ERROR response means nothing was registered, so there is nothing to clean up either
+    let announce_resp = match client.announce(announce_request.clone()).await {
+        Err(UdpTrackerClientError::ErrorResponse(message)) if options.alive_on_error =>
+            return Ok(ProbeResponse::error_response(timestamp.elapsed(), message)),
+        announce_resp => announce_resp?
+    };
+
+    // the samples share the connection, so the RTT is the CONNECT plus the median ANNOUNCE round trip,
+    // the same two round trips a single sample measures
+    let mut announce_rtts = vec![timestamp.elapsed() - connect_rtt];
+    for sample in 2..=options.rtt_samples {
+        let timestamp = Instant::now();
+        match client.announce(announce_request.clone()).await {
+            Ok(_) => announce_rtts.push(timestamp.elapsed()),
+            Err(err) => debug!("RTT sample {}/{} of {} failed: {:?}", sample, options.rtt_samples, address, err)
+        }
+    }
+    let rtt = connect_rtt + median(announce_rtts);
+
+    let is_local_peer_returned = announce_resp.peers.iter()
+        .any(|peer| local_port.port() == peer.port());
+
+    // we clean up after ourselves by removing the announce, whether or not it proved liveness
+    let announce_request = AnnounceRequest::new(
+        info_hash,
+        peer_id,
+        ClientState::new(0, 100, 0, AnnounceEvent::Stopped),
+        source_ip,
+        key,
+        DesiredPeers::Default,
+        local_port.port(),
+        AnnounceOptions::new()
+    );
+    for attempt in 1..=CLEANUP_ATTEMPTS {
+        match client.announce(announce_request.clone()).await {
+            Ok(_) => break,
+            Err(err) => debug!("Cleanup announce {}/{} to {} failed: {:?}", attempt, CLEANUP_ATTEMPTS, address, err)
+        }
+    }
+
+    if is_local_peer_returned || options.info_hash.is_some() {
+        Ok(ProbeResponse {
+            rtt,
+            seeders: u32::try_from(announce_resp.seeders).ok(),
+            leechers: u32::try_from(announce_resp.leechers).ok(),
+            peer_count: u32::try_from(announce_resp.peers.len()).ok(),
+            interval: u32::try_from(announce_resp.interval).ok(),
+            min_interval: u32::try_from(announce_resp.interval).ok(),
+            peer_encoding: None,
+            tracker_error: None,
+            redirect: None
+        })
+    } else {
+        Err(CheckError::NoSelfPeer)
+    }
+}
+
+/// HTTP trackers commonly omit the announcer from the returned peers,
+/// so a well-formed response carrying an `interval` is taken as proof of liveness.
+/// One redirect is followed, and the profile then describes the tracker it led to, in `redirected_to`.
+/// An HTTPS one can't be announced to, so it fails the check
+pub async fn check_http_candidate(candidate: TrackerCandidate, options: &CheckOptions) -> Result<CandidateProfile, CheckError> {
+    let (addrs, responses) = announce_http_candidate(&candidate, options).await?;
+
+    let location = responses.iter()
+        .find_map(|response| response.as_ref().ok().and_then(|response| response.redirect.clone()));
+    let location = match location {
+        Some(location) => location,
+        None => return profile_from_responses(candidate, addrs, check_results(responses), options.address_policy)
+    };
+
+    let target = redirect_target(&candidate, &location)
+        .map_err(|err| CheckError::OperationalError(format!("Redirect to an invalid location '{}': {}", location, err)))?;
+    debug!("{} redirects to {}", candidate, target);
+    let mut profile = match target.transport_type {
+        TransportType::HTTPS => return Err(CheckError::OperationalError(String::from(HTTPS_UNSUPPORTED))),
+        TransportType::HTTP => {
+            // a second redirect fails the check like any other non-200 status
+            let (addrs, responses) = announce_http_candidate(&target, options).await?;
+            profile_from_responses(candidate, addrs, check_results(responses), options.address_policy)?
+        }
+        TransportType::UDP => return Err(CheckError::OperationalError(format!("Redirect to {} is not HTTP", target)))
+    };
+    profile.redirected_to = Some(target);
+    Ok(profile)
+}
+
+async fn announce_http_candidate(
+    candidate: &TrackerCandidate,
+    options: &CheckOptions
+) -> Result<(Vec<SocketAddr>, Vec<Result<ProbeResponse, HttpTrackerClientError>>), CheckError> {
+    let addrs = resolve_candidate(candidate, options).await?;
+
+    let responses = addrs.iter().map(|address| {
+        async move {
+            let mut client = HttpTrackerClient::new(candidate, address).with_timeout(options.timeout);
+            if let Some(pool) = &options.http_pool {
+                client = client.with_pool(pool);
+            }
+            let timestamp = Instant::now();
+
+            let info_hash = probe_info_hash(options);
+            let peer_id = random_peer_id();
+            let port = 6881;
 
-        if is_local_peer_returned {
-            // we clean up after ourselves by removing the announce
+            let announce_request = AnnounceRequest::new(
+                info_hash,
+                peer_id,
+                ClientState::new(0, 100, 0, options.announce_event),
+                SourceIP::ImpliedV4,
+                0,
+                options.num_want,
+                port,
+                AnnounceOptions::new()
+            );
+
+            let announce_resp = match client.announce(announce_request.clone()).await {
+                Err(HttpTrackerClientError::ApplicationError(reason)) if options.compact == CompactMode::Both => {
+                    debug!("Compact announce to {} rejected, retrying without: {}", address, reason);
+                    client = client.with_compact(false);
+                    client.announce(announce_request).await
+                }
+                announce_resp => announce_resp
+            };
+            // nothing was registered, so there is nothing to clean up either
+            let announce_resp = match announce_resp {
+                Err(HttpTrackerClientError::Redirect(location)) => return Ok(ProbeResponse::redirect_response(timestamp.elapsed(), location)),
+                announce_resp => announce_resp?
+            };
+
+            let rtt = timestamp.elapsed();
+
+            // we clean up after ourselves by removing the announce, in the encoding that was accepted
             let announce_request = AnnounceRequest::new(
                 info_hash,
                 peer_id,
                 ClientState::new(0, 100, 0, AnnounceEvent::Stopped),
-                source_ip,
+                SourceIP::ImpliedV4,
                 0,
                 DesiredPeers::Default,
-                local_port.port(),
+                port,
                 AnnounceOptions::new()
             );
-            client.announce(announce_request).await;
-            Ok((address, rtt))
-        } else {
-            Err(CheckError::OperationalError)
+            for attempt in 1..=CLEANUP_ATTEMPTS {
+                match client.announce(announce_request.clone()).await {
+                    Ok(_) => break,
+                    Err(err) => debug!("Cleanup announce {}/{} to {} failed: {:?}", attempt, CLEANUP_ATTEMPTS, address, err)
+                }
+            }
+            Ok(ProbeResponse {
+                rtt,
+                seeders: announce_resp.seeders.and_then(|seeders| u32::try_from(seeders).ok()),
+                leechers: announce_resp.leechers.and_then(|leechers| u32::try_from(leechers).ok()),
+                peer_count: u32::try_from(announce_resp.peers.len()).ok(),
+                interval: u32::try_from(announce_resp.interval).ok(),
+                min_interval: u32::try_from(announce_resp.min_interval.unwrap_or(announce_resp.interval)).ok(),
+                peer_encoding: announce_resp.peer_encoding,
+                tracker_error: None,
+                redirect: None
+            })
         }
     }).collect::<Vec<_>>();
 
     let responses = futures::future::join_all(responses).await;
+    Ok((addrs, responses))
+}
+
+/// Redirects count as failures, like any other non-200 status
+fn check_results(responses: Vec<Result<ProbeResponse, HttpTrackerClientError>>) -> Vec<Result<ProbeResponse, CheckError>> {
+    responses.into_iter()
+        .map(|response| match response {
+            Ok(ProbeResponse { redirect: Some(location), .. }) => Err(CheckError::from(HttpTrackerClientError::Redirect(location))),
+            response => response.map_err(CheckError::from)
+        })
+        .collect()
+}
+
+/// `Location` may be relative to the candidate, and carries our announce query, which is dropped
+fn redirect_target(candidate: &TrackerCandidate, location: &str) -> Result<TrackerCandidate, &'static str> {
+    let location = location.split(['?', '#']).next().unwrap_or_default();
+    if location.starts_with("//") {
+        TrackerCandidate::from_url(&format!("http:{}", location))
+    } else if location.starts_with('/') {
+        TrackerCandidate::from_url(&format!("http://{}{}", candidate.authority(), location))
+    } else {
+        TrackerCandidate::from_url(location)
+    }
+}
+
+/// A proxy which can't be reached or refuses the association is our problem, not the tracker's,
+/// but it still fails the check
+async fn associate(proxy: &Socks5Proxy, timeout: Duration) -> Result<UdpAssociation, CheckError> {
+    let association = async {
+        let proxy_addr = proxy.resolve().await?;
+        socks5::udp_associate(&proxy_addr).await
+    };
+    match time::timeout(timeout, association).await {
+        Ok(Ok(association)) => Ok(association),
+        Ok(Err(err)) => Err(CheckError::OperationalError(format!("SOCKS5 proxy {}:{} failed: {}", proxy.host, proxy.port, err))),
+        Err(_) => Err(CheckError::OperationalError(format!("SOCKS5 proxy {}:{} timed out", proxy.host, proxy.port)))
+    }
+}
+
+/// A fresh peer id for every announce: trackers that dedup on peer id could otherwise answer
+/// a concurrent check, or a re-run, with the stale peer registered by the previous one,
+/// which would fool the local-peer liveness check
+fn random_peer_id() -> PeerId {
+    PeerId::from(rand::random::<[u8; 20]>())
+}
+
+fn median(mut durations: Vec<Duration>) -> Duration {
+    durations.sort_unstable();
+    let middle = durations.len() / 2;
+    if durations.len().is_multiple_of(2) {
+        (durations[middle - 1] + durations[middle]) / 2
+    } else {
+        durations[middle]
+    }
+}
+
+/// Some trackers reject announces with a zero key
+fn random_key() -> u32 {
+    rand::random::<u32>().max(1)
+}
+
+fn probe_info_hash(options: &CheckOptions) -> InfoHash {
+    options.info_hash.unwrap_or_else(|| InfoHash::from_bytes("tracker_test".as_bytes()))
+}
+
+async fn resolve_candidate(candidate: &TrackerCandidate, options: &CheckOptions) -> Result<Vec<SocketAddr>, CheckError> {
+    let addrs = resolve_host_cached(&candidate.host, options).await?
+        .into_iter()
+        .filter(|addr| options.family.includes(&addr.ip()))
+        .map(|mut addr| {
+            addr.set_port(candidate.port);
+            addr
+        })
+        .collect::<Vec<_>>();
+    // a host without any address of the family we check can't be reached, just like an unresolved one
+    if addrs.is_empty() { return Err(CheckError::DnsResolutionFailed); }
+    Ok(addrs)
+}
+
+async fn resolve_host_cached(host: &str, options: &CheckOptions) -> Result<Vec<SocketAddr>, CheckError> {
+    let cache = match &options.dns_cache {
+        Some(cache) => cache,
+        None => return resolve_host(host, options.dns_timeout).await
+    };
+    if let Some(cached) = cache.entries.lock().await.get(host) {
+        cache.hits.fetch_add(1, Ordering::Relaxed);
+        return cached.clone();
+    }
+    cache.misses.fetch_add(1, Ordering::Relaxed);
+    let resolved = resolve_host(host, options.dns_timeout).await;
+    if resolved != Err(CheckError::DnsTimeout) {
+        cache.entries.lock().await.insert(String::from(host), resolved.clone());
+    }
+    resolved
+}
+
+async fn resolve_host(host: &str, dns_timeout: Duration) -> Result<Vec<SocketAddr>, CheckError> {
+    let addrs = time::timeout(dns_timeout, lookup_host((host, 0))).await
+        .map_err(|_| CheckError::DnsTimeout)?
+        .map_err(|_| CheckError::DnsResolutionFailed)?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() { return Err(CheckError::DnsResolutionFailed); }
+    Ok(addrs)
+}
+
+/// Fast LAN trackers answer well within a millisecond, so the microseconds are kept
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+fn profile_from_responses(
+    candidate: TrackerCandidate,
+    addrs: Vec<SocketAddr>,
+    responses: Vec<Result<ProbeResponse, CheckError>>,
+    address_policy: AddressPolicy
+) -> Result<CandidateProfile, CheckError> {
+    let address_results = addrs.iter()
+        .zip(responses.iter())
+        .map(|(addr, response)| AddressResult {
+            addr: *addr,
+            result: response.as_ref().map(|response| as_millis(response.rtt)).map_err(Clone::clone),
+        })
+        .collect::<Vec<_>>();
+
+    address_results.iter()
+        .filter_map(|address_result| address_result.result.as_ref().err().map(|err| (address_result.addr, err)))
+        .for_each(|(addr, err)| debug!("Address {} of {} failed: {:?}", addr, candidate, err));
 
     let ok_count = responses.iter()
         .filter(|response| { response.is_ok() })
         .count();
 
-    if ok_count == responses.len() {
-        let rtt_ms = responses.iter()
+    // the addresses we can't route to say nothing about the tracker, so they don't count either way
+    let reachable = responses.iter()
+        .filter(|response| !matches!(response, Err(CheckError::Unreachable)))
+        .count();
+    if reachable == 0 {
+        return Err(CheckError::Unreachable);
+    }
+
+    let is_accepted = match address_policy {
+        AddressPolicy::All => ok_count == reachable,
+        AddressPolicy::Any => ok_count > 0,
+    };
+
+    if is_accepted {
+        let answered = responses.iter()
             .filter_map(|response| response.as_ref().ok())
-            .map(|response| response.1)
-            .map(|duration| duration.as_millis() as u32)
-            .sum::<u32>() / responses.len() as u32;
+            .collect::<Vec<_>>();
+        let rtt_ms = as_millis(answered.iter().map(|response| response.rtt).sum::<Duration>() / ok_count as u32);
+
+        let addrs = address_results.iter()
+            .filter(|address_result| address_result.result.is_ok())
+            .map(|address_result| address_result.addr)
+            .collect();
 
         return Ok(CandidateProfile {
             candidate,
             addrs,
             rtt_ms,
+            address_results,
+            seeders: answered[0].seeders,
+            leechers: answered[0].leechers,
+            peer_count: answered[0].peer_count,
+            interval: answered[0].interval,
+            min_interval: answered[0].min_interval,
+            peer_encoding: answered[0].peer_encoding,
+            tracker_error: answered.iter().find_map(|response| response.tracker_error.clone()),
+            redirected_to: None,
         });
     }
 
-    let op_errors = responses.iter()
-        .filter_map(|response| response.clone().err())
-        .filter(|err| err == &CheckError::OperationalError)
-        .count();
+    let op_error = responses.iter()
+        .filter_map(|response| response.as_ref().err())
+        .find(|err| matches!(err, CheckError::OperationalError(_) | CheckError::OversizedResponse(_)));
+
+    if let Some(op_error) = op_error {
+        return Err(op_error.clone());
+    }
 
-    if op_errors > 0 {
-        return Err(CheckError::OperationalError);
+    if responses.iter().any(|response| matches!(response, Err(CheckError::NoSelfPeer))) {
+        return Err(CheckError::NoSelfPeer);
     }
 
     let timeouts = responses.iter()
-        .filter_map(|response| response.clone().err())
-        .filter(|err| err == &CheckError::Timeout)
+        .filter(|response| matches!(response, Err(CheckError::Timeout)))
         .count();
 
-    if timeouts < responses.len() {
-        return Err(CheckError::PartialTimeout);
+    if timeouts < reachable {
+        return Err(CheckError::PartialTimeout { ok: ok_count, timed_out: timeouts, total: reachable });
     }
 
-    return Err(CheckError::Timeout);
+    Err(CheckError::Timeout)
 }
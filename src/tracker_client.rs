@@ -1,30 +1,52 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::Error;
 use std::io::ErrorKind::TimedOut;
 use std::net::SocketAddr;
-use std::time::{Duration, SystemTime};
-use bip_util::bt::{InfoHash, PeerId};
-use bip_utracker::{announce, contact, request, response};
-use bip_utracker::announce::{AnnounceRequest};
-use bip_utracker::contact::CompactPeers;
-use bip_utracker::request::CONNECT_ID_PROTOCOL_ID;
-use bip_utracker::request::RequestType::Connect;
+use std::time::{Duration, Instant};
+use bip_utracker::{request, response};
+use bip_utracker::announce::{AnnounceRequest, DesiredPeers};
+use bip_utracker::scrape::ScrapeRequest;
+use log::trace;
 use nom::IResult;
 use tokio::net::UdpSocket;
 use tokio::time;
 use tokio::time::error::Elapsed;
-use self::UdpTrackerClientError::{ApplicationError, GeneralError};
-use std::convert::TryInto;
+use crate::socks5;
+use self::UdpTrackerClientError::{ApplicationError, ErrorResponse, GeneralError, OversizedResponse};
 
+/// An announce response with no peers, the smallest one a tracker can send back
+pub const ANNOUNCE_HEADER_SIZE: usize = 20;
+const COMPACT_PEER_V6_SIZE: usize = 18;
+/// Leaves room for ERROR responses, whatever the number of peers asked for
+const MIN_BUFFER_SIZE: usize = 1024;
+/// Largest payload a UDP datagram can carry
+const MAX_DATAGRAM_SIZE: usize = 65507;
+/// BEP 15 responses are a single datagram, so asking for more peers than fit in one is pointless
+pub const MAX_NUM_WANT: usize = (MAX_DATAGRAM_SIZE - ANNOUNCE_HEADER_SIZE) / COMPACT_PEER_V6_SIZE;
+
+/// BEP 15: a connection id may be used for one minute after it was received
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Not yet connected: `connect` turns it into the `ConnectedClient` which can announce and scrape
 pub struct UdpTrackerClient<'a> {
     socket: &'a UdpSocket,
     tracker_addr: &'a SocketAddr,
-    conn_id: u64,
     timeout: Duration,
+    /// SOCKS5 UDP relay every datagram goes through, instead of straight to the tracker
+    relay_addr: Option<SocketAddr>,
+    /// Largest ANNOUNCE response processed, on top of the size the peers asked for would take
+    max_response_size: Option<usize>,
+}
+
+/// Holds the connection id of a CONNECT exchange, and renews it once it expires.
+/// Callers issuing several announces or scrapes should reuse one client, each CONNECT costs a round trip
+pub struct ConnectedClient<'a> {
+    client: UdpTrackerClient<'a>,
+    conn_id: u64,
+    connected_at: Instant,
 }
 
+/// BEP 15 has no `min interval`, `interval` is all a UDP tracker says about announce pacing
 pub struct AnnounceResponse {
     pub interval: i32,
     pub leechers: i32,
@@ -37,87 +59,208 @@ impl<'a> UdpTrackerClient<'a> {
         Self {
             socket,
             tracker_addr,
-            conn_id: 0,
-            timeout: Duration::from_secs(5)
+            timeout: Duration::from_secs(5),
+            relay_addr: None,
+            max_response_size: None
         }
     }
 
-    pub async fn connect(&mut self) -> UdpTrackerClientResult<()> {
-        let mut buffer = [0u8; 1024];
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The caller keeps the `socks5::UdpAssociation` behind `relay_addr` alive while the client is in use
+    pub fn with_relay(mut self, relay_addr: SocketAddr) -> Self {
+        self.relay_addr = Some(relay_addr);
+        self
+    }
+
+    /// Keeps a tracker which answers with far more than we asked for from using us to measure its reflection
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = Some(max_response_size);
+        self
+    }
+
+    pub async fn connect(self) -> UdpTrackerClientResult<ConnectedClient<'a>> {
+        let conn_id = self.request_conn_id().await?;
+        Ok(ConnectedClient {
+            client: self,
+            conn_id,
+            connected_at: Instant::now()
+        })
+    }
+
+    async fn request_conn_id(&self) -> UdpTrackerClientResult<u64> {
+        // the extra byte tells a response that exactly fills the buffer apart from a truncated one
+        let mut buffer = [0u8; MIN_BUFFER_SIZE + 1];
 
         let transaction_id = UdpTrackerClient::create_random_transaction_id();
 
+        let mut request_bytes = Vec::new();
         request::TrackerRequest::new(
             request::CONNECT_ID_PROTOCOL_ID,
             transaction_id,
             request::RequestType::Connect,
-        ).write_bytes(&mut buffer[..]).expect("Buffer has sufficient space for CONNECT request");
+        ).write_bytes(&mut request_bytes).expect("Writing the CONNECT request to memory to succeed");
 
-        if buffer.len() != self.socket.send_to(&buffer, self.tracker_addr).await? {
+        if request_bytes.len() != self.send(&request_bytes).await? {
             return Err(GeneralError("Failed to send the entire CONNECT request"))
         };
 
-        let read = time::timeout(self.timeout, self.socket.recv(&mut buffer)).await??;
-        if read > buffer.len() {
+        let read = self.recv_matching(&mut buffer, transaction_id).await?;
+        if read >= buffer.len() {
             return Err(GeneralError("Failed to read the entire CONNECT response. Buffer too small?"))
         }
 
         let response = response::TrackerResponse::from_bytes(&buffer[0..read]);
         let response = match response {
             IResult::Done(_, output) => Ok(output),
-            IResult::Incomplete(_) => Err(ApplicationError("Incomplete CONNECT response")),
-            IResult::Error(_) => Err(ApplicationError("Unknown CONNECT response error"))
+            IResult::Incomplete(_) => Err(ApplicationError(String::from("Incomplete CONNECT response"))),
+            IResult::Error(_) => Err(ApplicationError(String::from("Unknown CONNECT response error")))
         }?;
 
         let conn_id = match response.response_type() {
             response::ResponseType::Connect(conn_id) => Ok(*conn_id),
-            response::ResponseType::Announce(_) => Err(ApplicationError("Expected CONNECT response, got ANNOUNCE response")),
-            response::ResponseType::Scrape(_) => Err(ApplicationError("Expected CONNECT response, got SCRAPE response")),
-            response::ResponseType::Error(_) => Err(ApplicationError("Expected CONNECT response, got ERROR response"))
+            response::ResponseType::Announce(_) => Err(ApplicationError(String::from("Expected CONNECT response, got ANNOUNCE response"))),
+            response::ResponseType::Scrape(_) => Err(ApplicationError(String::from("Expected CONNECT response, got SCRAPE response"))),
+            response::ResponseType::Error(err) => Err(ErrorResponse(String::from(err.message())))
         }?;
 
-        self.conn_id = conn_id;
-        Ok(())
+        Ok(conn_id)
     }
 
-    pub async fn announce(&self, announce_req: AnnounceRequest<'_>) -> UdpTrackerClientResult<AnnounceResponse> {
-        if self.conn_id == 0 {
-            return Err(ApplicationError("You have to run connect first!"));
+    /// BEP 15 requires discarding any packet whose transaction id doesn't match the request,
+    /// so we keep reading until a matching one arrives or the timeout elapses
+    async fn recv_matching(&self, buffer: &mut [u8], transaction_id: u32) -> UdpTrackerClientResult<usize> {
+        let deadline = time::Instant::now() + self.timeout;
+        let mut mismatched = false;
+        loop {
+            let read = match time::timeout_at(deadline, self.recv(buffer)).await {
+                Ok(read) => read?,
+                Err(_) if mismatched => return Err(ApplicationError(String::from("Transaction id mismatch"))),
+                Err(elapsed) => return Err(elapsed.into())
+            };
+            // through a relay `read` may exceed the buffer, which holds only what fit
+            let received = &buffer[..read.min(buffer.len())];
+            if received.len() >= 8 && received[4..8] == transaction_id.to_be_bytes() {
+                trace!("Received {} bytes from {}", read, self.tracker_addr);
+                return Ok(read);
+            }
+            trace!("Discarded {} bytes from {} not answering transaction {}", read, self.tracker_addr, transaction_id);
+            mismatched = true;
         }
+    }
 
-        let mut buffer = [0u8; 1024];
+    /// Returns the number of payload bytes sent, not counting any relay header
+    async fn send(&self, payload: &[u8]) -> io::Result<usize> {
+        trace!("Sending {} bytes to {}", payload.len(), self.tracker_addr);
+        match &self.relay_addr {
+            None => self.socket.send_to(payload, self.tracker_addr).await,
+            Some(relay_addr) => {
+                let datagram = socks5::wrap_datagram(self.tracker_addr, payload);
+                let sent = self.socket.send_to(&datagram, relay_addr).await?;
+                Ok(sent.saturating_sub(datagram.len() - payload.len()))
+            }
+        }
+    }
+
+    /// Returns the length of the payload received. A truncated one shows as filling the buffer, the socket
+    /// cuts it to the buffer's length, or exceeding it, the relay reports the full length.
+    /// Buffers are sized one byte past the largest response expected, so a complete one never fills them
+    async fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.relay_addr.is_none() {
+            return self.socket.recv(buffer).await;
+        }
+        let mut datagram = vec![0u8; buffer.len() + socks5::MAX_HEADER_SIZE];
+        loop {
+            let read = self.socket.recv(&mut datagram).await?;
+            // the relay is the only sender we expect, anything without a valid header is noise
+            if let Some(header_len) = socks5::header_len(&datagram[..read]) {
+                let payload = &datagram[header_len..read];
+                let copied = payload.len().min(buffer.len());
+                buffer[..copied].copy_from_slice(&payload[..copied]);
+                return Ok(payload.len());
+            }
+        }
+    }
+
+    /// Sized for the peers we asked for, with every peer in the 18 byte IPv6 form.
+    /// The extra byte tells a response that exactly fills the buffer apart from a truncated one
+    fn announce_buffer_size(num_want: DesiredPeers) -> usize {
+        let size = match num_want {
+            DesiredPeers::Specified(count) if count >= 0 => ANNOUNCE_HEADER_SIZE + COMPACT_PEER_V6_SIZE * count as usize,
+            _ => MAX_DATAGRAM_SIZE
+        };
+        size.clamp(MIN_BUFFER_SIZE, MAX_DATAGRAM_SIZE) + 1
+    }
+
+    fn create_random_transaction_id() -> u32 {
+        rand::random::<u32>()
+    }
+
+}
+
+impl<'a> ConnectedClient<'a> {
+    pub fn conn_id(&self) -> u64 {
+        self.conn_id
+    }
+
+    pub fn is_connection_valid(&self) -> bool {
+        self.connected_at.elapsed() < CONNECTION_ID_LIFETIME
+    }
+
+    pub async fn announce(&mut self, announce_req: AnnounceRequest<'_>) -> UdpTrackerClientResult<AnnounceResponse> {
+        let conn_id = self.ensure_connected().await?;
+
+        let num_want = announce_req.num_want();
+        let mut buffer_size = UdpTrackerClient::announce_buffer_size(num_want);
+        if let Some(max_response_size) = self.client.max_response_size {
+            buffer_size = buffer_size.min(max_response_size + 1);
+        }
+        let mut buffer = vec![0u8; buffer_size];
 
         let transaction_id = UdpTrackerClient::create_random_transaction_id();
 
+        let mut request_bytes = Vec::new();
         request::TrackerRequest::new(
-            self.conn_id,
+            conn_id,
             transaction_id,
             request::RequestType::Announce(announce_req),
-        ).write_bytes(&mut buffer[..]).expect("Buffer has sufficient space for ANNOUNCE request");
+        ).write_bytes(&mut request_bytes).expect("Writing the ANNOUNCE request to memory to succeed");
 
-        if buffer.len() != self.socket.send_to(&buffer, self.tracker_addr).await? {
+        if request_bytes.len() != self.client.send(&request_bytes).await? {
             return Err(GeneralError("Failed to send the entire ANNOUNCE request"))
         };
 
-        let read = time::timeout(self.timeout, self.socket.recv(&mut buffer)).await??;
+        let read = self.client.recv_matching(&mut buffer, transaction_id).await?;
+        // the buffer fits the peers we asked for, anything filling it sent more
         if read >= buffer.len() {
-            return Err(GeneralError("Failed to read the entire ANNOUNCE response. Buffer too small?"))
+            return Err(OversizedResponse(format!("ANNOUNCE response over the {} bytes expected", buffer.len() - 1)))
         }
 
         let response = response::TrackerResponse::from_bytes(&buffer[0..read]);
         let response = match response {
             IResult::Done(_, output) => Ok(output),
-            IResult::Incomplete(_) => Err(ApplicationError("Incomplete ANNOUNCE response")),
-            IResult::Error(_) => Err(ApplicationError("Unknown ANNOUNCE response error"))
+            IResult::Incomplete(_) => Err(ApplicationError(String::from("Incomplete ANNOUNCE response"))),
+            IResult::Error(_) => Err(ApplicationError(String::from("Unknown ANNOUNCE response error")))
         }?;
 
         let announce_response = match response.response_type() {
             response::ResponseType::Announce(announce_response) => Ok(announce_response),
-            response::ResponseType::Connect(_) => Err(ApplicationError("Expected ANNOUNCE response, got CONNECT response")),
-            response::ResponseType::Scrape(_) => Err(ApplicationError("Expected ANNOUNCE response, got SCRAPE response")),
-            response::ResponseType::Error(_) => Err(ApplicationError("Expected ANNOUNCE response, got ERROR response"))
+            response::ResponseType::Connect(_) => Err(ApplicationError(String::from("Expected ANNOUNCE response, got CONNECT response"))),
+            response::ResponseType::Scrape(_) => Err(ApplicationError(String::from("Expected ANNOUNCE response, got SCRAPE response"))),
+            response::ResponseType::Error(err) => Err(ErrorResponse(String::from(err.message())))
         }?;
 
+        // IPv4 peers take a third of the room the buffer leaves for each, so the count is checked too
+        let peer_count = announce_response.peers().iter().count();
+        match num_want {
+            DesiredPeers::Specified(num_want) if num_want >= 0 && peer_count > num_want as usize =>
+                return Err(OversizedResponse(format!("ANNOUNCE response with {} peers, {} asked for", peer_count, num_want))),
+            _ => {}
+        }
+
         let peers = announce_response.peers().iter().collect::<Vec<_>>();
         Ok(AnnounceResponse {
             interval: announce_response.interval(),
@@ -127,23 +270,71 @@ impl<'a> UdpTrackerClient<'a> {
         })
     }
 
-    /// Maybe worth replacing with the `rand` crate in the future
-    /// Since this has zero security implications, it is good enough for now
-    fn create_random_transaction_id() -> u32 {
-        let mut hasher = DefaultHasher::default();
-        SystemTime::now().hash(&mut hasher);
-        hasher.finish() as u32
+    /// Returns the per-hash `(seeders, completed, leechers)` in the order the hashes were inserted into the request
+    pub async fn scrape(&mut self, scrape_req: ScrapeRequest<'_>) -> UdpTrackerClientResult<Vec<(i32, i32, i32)>> {
+        let conn_id = self.ensure_connected().await?;
+
+        let mut buffer = [0u8; MIN_BUFFER_SIZE + 1];
+
+        let transaction_id = UdpTrackerClient::create_random_transaction_id();
+
+        let mut request_bytes = Vec::new();
+        request::TrackerRequest::new(
+            conn_id,
+            transaction_id,
+            request::RequestType::Scrape(scrape_req),
+        ).write_bytes(&mut request_bytes).expect("Writing the SCRAPE request to memory to succeed");
+
+        if request_bytes.len() != self.client.send(&request_bytes).await? {
+            return Err(GeneralError("Failed to send the entire SCRAPE request"))
+        };
+
+        let read = self.client.recv_matching(&mut buffer, transaction_id).await?;
+        if read >= buffer.len() {
+            return Err(GeneralError("Failed to read the entire SCRAPE response. Buffer too small?"))
+        }
+
+        let response = response::TrackerResponse::from_bytes(&buffer[0..read]);
+        let response = match response {
+            IResult::Done(_, output) => Ok(output),
+            IResult::Incomplete(_) => Err(ApplicationError(String::from("Incomplete SCRAPE response"))),
+            IResult::Error(_) => Err(ApplicationError(String::from("Unknown SCRAPE response error")))
+        }?;
+
+        let scrape_response = match response.response_type() {
+            response::ResponseType::Scrape(scrape_response) => Ok(scrape_response),
+            response::ResponseType::Connect(_) => Err(ApplicationError(String::from("Expected SCRAPE response, got CONNECT response"))),
+            response::ResponseType::Announce(_) => Err(ApplicationError(String::from("Expected SCRAPE response, got ANNOUNCE response"))),
+            response::ResponseType::Error(err) => Err(ErrorResponse(String::from(err.message())))
+        }?;
+
+        Ok(scrape_response.iter()
+            .map(|stats| (stats.num_seeders(), stats.num_downloads(), stats.num_leechers()))
+            .collect())
     }
 
+    /// Returns a usable connection id, refreshing an expired one
+    async fn ensure_connected(&mut self) -> UdpTrackerClientResult<u64> {
+        if !self.is_connection_valid() {
+            self.conn_id = self.client.request_conn_id().await?;
+            self.connected_at = Instant::now();
+        }
+        Ok(self.conn_id)
+    }
 }
 
 pub type UdpTrackerClientResult<T> = Result<T, UdpTrackerClientError>;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum UdpTrackerClientError {
     GeneralError(&'static str),
     IoError(io::Error),
-    ApplicationError(&'static str)
+    ApplicationError(String),
+    /// A well-formed ERROR response, with the tracker's message
+    ErrorResponse(String),
+    /// More than the request asked for, or than `with_max_response_size` allows
+    OversizedResponse(String)
 }
 
 impl From<io::Error> for UdpTrackerClientError {
@@ -156,4 +347,150 @@ impl From<Elapsed> for UdpTrackerClientError {
     fn from(_: Elapsed) -> Self {
        UdpTrackerClientError::IoError(io::Error::new(TimedOut, ""))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use bip_util::bt::{InfoHash, PeerId};
+    use bip_utracker::announce::{AnnounceEvent, ClientState, SourceIP};
+    use bip_utracker::option::AnnounceOptions;
+    use super::*;
+
+    const CONNECTION_ID: u64 = 4242;
+
+    /// Binds a mock tracker on 127.0.0.1 which answers every datagram with the ones `respond` returns
+    async fn mock_tracker(respond: fn(&[u8]) -> Vec<Vec<u8>>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 2048];
+            loop {
+                let (read, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                for datagram in respond(&buffer[..read]) {
+                    socket.send_to(&datagram, peer).await.unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    /// The CONNECT response to `request`, handing out `conn_id`
+    fn connect_response(request: &[u8], conn_id: u64) -> Vec<u8> {
+        [&0u32.to_be_bytes()[..], &request[12..16], &conn_id.to_be_bytes()].concat()
+    }
+
+    /// The ANNOUNCE response to `request`, listing `peers` IPv4 peers
+    fn announce_response(request: &[u8], peers: usize) -> Vec<u8> {
+        let mut response = [&1u32.to_be_bytes()[..], &request[12..16], &1800u32.to_be_bytes(), &0u32.to_be_bytes(), &1u32.to_be_bytes()].concat();
+        (0..peers).for_each(|peer| response.extend_from_slice(&[10, 0, (peer / 256) as u8, (peer % 256) as u8, 0x1a, 0xe1]));
+        response
+    }
+
+    fn announce_request(num_want: i32) -> AnnounceRequest<'static> {
+        AnnounceRequest::new(
+            InfoHash::from([1u8; 20]),
+            PeerId::from([2u8; 20]),
+            ClientState::new(0, 100, 0, AnnounceEvent::Started),
+            SourceIP::ImpliedV4,
+            0,
+            DesiredPeers::Specified(num_want),
+            6881,
+            AnnounceOptions::new()
+        )
+    }
+
+    async fn client_socket() -> UdpSocket {
+        UdpSocket::bind("127.0.0.1:0").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn skips_responses_to_other_transactions() {
+        let addr = mock_tracker(|request| {
+            let mut mismatched = connect_response(request, CONNECTION_ID + 1);
+            mismatched[4] ^= 0xff;
+            vec![mismatched, connect_response(request, CONNECTION_ID)]
+        }).await;
+        let socket = client_socket().await;
+        let client = UdpTrackerClient::new(&socket, &addr).connect().await.unwrap();
+        assert_eq!(client.conn_id(), CONNECTION_ID);
+    }
+
+    #[tokio::test]
+    async fn reports_only_mismatched_responses() {
+        let addr = mock_tracker(|request| {
+            let mut mismatched = connect_response(request, CONNECTION_ID);
+            mismatched[4] ^= 0xff;
+            vec![mismatched]
+        }).await;
+        let socket = client_socket().await;
+        let res = UdpTrackerClient::new(&socket, &addr).with_timeout(Duration::from_millis(100)).connect().await;
+        assert!(matches!(res, Err(ApplicationError(message)) if message == "Transaction id mismatch"));
+    }
+
+    #[tokio::test]
+    async fn announces_with_a_zero_connection_id() {
+        // only announces carrying the connection id 0 it handed out are answered
+        let addr = mock_tracker(|request| match request[8..12] {
+            [0, 0, 0, 0] => vec![connect_response(request, 0)],
+            [0, 0, 0, 1] if request[..8] == 0u64.to_be_bytes() => vec![announce_response(request, 1)],
+            _ => Vec::new()
+        }).await;
+        let socket = client_socket().await;
+        let mut client = UdpTrackerClient::new(&socket, &addr).with_timeout(Duration::from_millis(500)).connect().await.unwrap();
+        assert_eq!(client.conn_id(), 0);
+        let response = client.announce(announce_request(5)).await.unwrap();
+        assert_eq!(response.peers.len(), 1);
+    }
+
+    #[test]
+    fn spreads_transaction_ids_evenly() {
+        let ids = (0..16_000).map(|_| UdpTrackerClient::create_random_transaction_id()).collect::<Vec<_>>();
+        // a handful of birthday collisions among 2^32 ids is expected, a clock-derived id would repeat all along
+        let distinct = ids.iter().collect::<std::collections::HashSet<_>>().len();
+        assert!(distinct >= ids.len() - 10, "{} distinct ids out of {}", distinct, ids.len());
+        // 1000 ids expected under each of the 16 top nibbles, more than 20% off would take a broken generator
+        let mut buckets = [0usize; 16];
+        ids.iter().for_each(|id| buckets[(id >> 28) as usize] += 1);
+        assert!(buckets.iter().all(|count| (800..=1200).contains(count)), "{:?}", buckets);
+    }
+
+    #[tokio::test]
+    async fn times_out_against_a_silent_tracker() {
+        // bound, so nothing answers, not even with an ICMP port unreachable
+        let silent = client_socket().await;
+        let addr = silent.local_addr().unwrap();
+        let socket = client_socket().await;
+        let res = UdpTrackerClient::new(&socket, &addr).with_timeout(Duration::from_millis(1)).connect().await;
+        match res {
+            Err(err) => assert_eq!(crate::tracker_check::CheckError::from(err), crate::tracker_check::CheckError::Timeout),
+            Ok(_) => panic!("Expected the CONNECT to time out")
+        }
+    }
+
+    #[tokio::test]
+    async fn parses_a_response_larger_than_the_minimum_buffer() {
+        // 1220 bytes, past the 1024 byte buffer announces once had
+        let addr = mock_tracker(|request| match request[8..12] {
+            [0, 0, 0, 0] => vec![connect_response(request, CONNECTION_ID)],
+            _ => vec![announce_response(request, 200)]
+        }).await;
+        let socket = client_socket().await;
+        let mut client = UdpTrackerClient::new(&socket, &addr).with_timeout(Duration::from_millis(500)).connect().await.unwrap();
+        let response = client.announce(announce_request(200)).await.unwrap();
+        assert_eq!(response.peers.len(), 200);
+    }
+    #[tokio::test]
+    async fn reports_responses_over_the_smallest_maximum_as_oversized() {
+        let addr = mock_tracker(|request| match request[8..12] {
+            [0, 0, 0, 0] => vec![connect_response(request, CONNECTION_ID)],
+            _ => vec![announce_response(request, 1)]
+        }).await;
+        let socket = client_socket().await;
+        let mut client = UdpTrackerClient::new(&socket, &addr)
+            .with_timeout(Duration::from_millis(500))
+            .with_max_response_size(ANNOUNCE_HEADER_SIZE)
+            .connect().await.unwrap();
+        let res = client.announce(announce_request(5)).await;
+        assert!(matches!(res, Err(OversizedResponse(_))), "{:?}", res.map(|response| response.peers.len()));
+    }
+}
@@ -0,0 +1,191 @@
+//! HTTP trackers checked through the real TCP path, against mock trackers on 127.0.0.1
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use always_online_torrent_trackers::candidates::{TrackerCandidate, TransportType};
+use always_online_torrent_trackers::http_tracker_client::{ConnectionPool, PeerEncoding};
+use always_online_torrent_trackers::tracker_check::{self, CheckError, CheckOptions, CompactMode};
+
+/// What the mock does with a connection once it has answered a request on it
+#[derive(Clone, Copy, PartialEq)]
+enum Connection {
+    /// Closes it, without a `Connection: close` to warn the client, like a server timing out idle connections
+    Close,
+    /// Waits for the next request on it
+    KeepAlive,
+}
+
+/// A mock HTTP tracker, answering every request with the bencoded body `respond` makes of its request line
+struct MockTracker {
+    addr: SocketAddr,
+    /// The request lines received so far, in order
+    requests: Arc<Mutex<Vec<String>>>,
+    /// Connections accepted so far
+    connections: Arc<AtomicUsize>,
+}
+
+impl MockTracker {
+    fn serve(respond: fn(&str) -> Vec<u8>, connection: Connection) -> MockTracker {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("The mock tracker to bind");
+        let addr = listener.local_addr().expect("The mock tracker to have an address");
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (received, accepted) = (requests.clone(), connections.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue
+                };
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let received = received.clone();
+                thread::spawn(move || {
+                    while let Some(request_line) = read_request(&mut stream) {
+                        received.lock().unwrap().push(request_line.clone());
+                        if stream.write_all(&http_response(&respond(&request_line))).is_err() || connection == Connection::Close {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        MockTracker { addr, requests, connections }
+    }
+
+    fn candidate(&self) -> TrackerCandidate {
+        TrackerCandidate::new(TransportType::HTTP, "127.0.0.1", self.addr.port(), Some("/announce"))
+            .expect("The mock tracker to be a valid candidate")
+    }
+
+    fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+/// Reads up to the end of the headers, GET requests have no body. `None` once the client hangs up
+fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => request.push(byte[0]),
+            _ => return None
+        }
+    }
+    String::from_utf8_lossy(&request).split("\r\n").next().map(String::from)
+}
+
+fn http_response(body: &[u8]) -> Vec<u8> {
+    [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).as_bytes(), body].concat()
+}
+
+/// The announcer, 127.0.0.1:6881, as the only compact peer
+fn compact_response(_request_line: &str) -> Vec<u8> {
+    [&b"d8:intervali1800e5:peers6:"[..], &[127, 0, 0, 1, 0x1a, 0xe1], b"e"].concat()
+}
+
+fn failure_response(_request_line: &str) -> Vec<u8> {
+    b"d14:failure reason6:bannede".to_vec()
+}
+
+/// Rejects `compact=1`, and lists the announcer as a dictionary peer otherwise
+fn dictionary_response(request_line: &str) -> Vec<u8> {
+    if request_line.contains("&compact=1") {
+        return b"d14:failure reason22:compact peers disablede".to_vec();
+    }
+    b"d8:intervali1800e5:peersld2:ip9:127.0.0.14:porti6881eeee".to_vec()
+}
+
+fn options() -> CheckOptions {
+    CheckOptions {
+        timeout: Duration::from_millis(500),
+        ..CheckOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn passes_a_compact_announce() {
+    let tracker = MockTracker::serve(compact_response, Connection::Close);
+    let profile = tracker_check::check_http_candidate(tracker.candidate(), &options()).await
+        .expect("The compact announce to pass");
+    assert_eq!(profile.interval, Some(1800));
+    assert_eq!(profile.peer_count, Some(1));
+    assert_eq!(profile.peer_encoding, Some(PeerEncoding::Compact));
+    // the probe, then the Stopped announce cleaning it up
+    let requests = tracker.requests();
+    assert_eq!(requests.len(), 2, "{:?}", requests);
+    assert!(requests[0].starts_with("GET /announce?info_hash=") && requests[0].contains("&compact=1&event=started"), "{}", requests[0]);
+    assert!(requests[1].contains("&event=stopped"), "{}", requests[1]);
+}
+
+#[tokio::test]
+async fn fails_on_a_failure_reason() {
+    let tracker = MockTracker::serve(failure_response, Connection::Close);
+    let res = tracker_check::check_http_candidate(tracker.candidate(), &options()).await;
+    match res {
+        Err(CheckError::OperationalError(reason)) => assert!(reason.contains("banned"), "{}", reason),
+        res => panic!("Expected the failure reason to fail the check, got {:?}", res)
+    }
+    // nothing was registered, so there is no cleanup announce
+    assert_eq!(tracker.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn retries_without_compact_peers() {
+    let tracker = MockTracker::serve(dictionary_response, Connection::Close);
+    let options = CheckOptions { compact: CompactMode::Both, ..options() };
+    let profile = tracker_check::check_http_candidate(tracker.candidate(), &options).await
+        .expect("The announce without compact=1 to pass");
+    assert_eq!(profile.peer_count, Some(1));
+    assert_eq!(profile.peer_encoding, Some(PeerEncoding::Dictionary));
+    // the rejected probe, its retry, then the cleanup in the encoding that was accepted
+    let requests = tracker.requests();
+    assert_eq!(requests.len(), 3, "{:?}", requests);
+    assert!(requests[0].contains("&compact=1"), "{}", requests[0]);
+    assert!(requests[1..].iter().all(|request| request.contains("&compact=0")), "{:?}", requests);
+}
+
+#[tokio::test]
+async fn fails_on_a_compact_rejection_without_the_fallback() {
+    let tracker = MockTracker::serve(dictionary_response, Connection::Close);
+    let res = tracker_check::check_http_candidate(tracker.candidate(), &options()).await;
+    match res {
+        Err(CheckError::OperationalError(reason)) => assert!(reason.contains("compact peers disabled"), "{}", reason),
+        res => panic!("Expected the rejection to fail the check, got {:?}", res)
+    }
+    assert_eq!(tracker.requests().len(), 1);
+}
+
+/// The probe and the Stopped announce go over a single connection
+#[tokio::test]
+async fn reuses_keep_alive_connections() {
+    let tracker = MockTracker::serve(compact_response, Connection::KeepAlive);
+    let pool = Arc::new(ConnectionPool::default());
+    let options = CheckOptions { http_pool: Some(pool.clone()), ..options() };
+    tracker_check::check_http_candidate(tracker.candidate(), &options).await
+        .expect("The announce to pass");
+    assert_eq!(tracker.requests().len(), 2);
+    assert_eq!(tracker.connections(), 1);
+    assert_eq!(pool.reused(), 1);
+}
+
+/// The pooled connection the mock closed fails once reused, and the Stopped announce reconnects
+#[tokio::test]
+async fn drops_connections_the_tracker_closed() {
+    let tracker = MockTracker::serve(compact_response, Connection::Close);
+    let pool = Arc::new(ConnectionPool::default());
+    let options = CheckOptions { http_pool: Some(pool.clone()), ..options() };
+    tracker_check::check_http_candidate(tracker.candidate(), &options).await
+        .expect("The announce to pass");
+    assert_eq!(tracker.requests().len(), 2);
+    assert_eq!(tracker.connections(), 2);
+    assert_eq!(pool.reused(), 0);
+}
@@ -0,0 +1,43 @@
+//! The `--self-test` scenarios, checking mock trackers through the real UDP socket path
+
+use always_online_torrent_trackers::self_test::SCENARIOS;
+
+async fn assert_passes(name: &str) {
+    let scenario = SCENARIOS.iter()
+        .find(|scenario| scenario.name == name)
+        .expect("The scenario to exist");
+    if let Err(failure) = scenario.run().await.expect("The mock tracker to bind") {
+        panic!("{}: {}", name, failure);
+    }
+}
+
+#[tokio::test]
+async fn announce() {
+    assert_passes("announce").await;
+}
+
+#[tokio::test]
+async fn scrape() {
+    assert_passes("scrape").await;
+}
+
+#[tokio::test]
+async fn mismatched_transaction_id() {
+    assert_passes("mismatched transaction id").await;
+}
+
+#[tokio::test]
+async fn dropped_first_datagram() {
+    assert_passes("dropped first datagram").await;
+}
+
+#[tokio::test]
+async fn no_self_peer() {
+    assert_passes("no self peer").await;
+}
+
+/// Three sampled announces and the Stopped one all go over a single CONNECT
+#[tokio::test]
+async fn connection_reuse() {
+    assert_passes("connection reuse").await;
+}